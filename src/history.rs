@@ -0,0 +1,275 @@
+use {
+    crate::price::Price,
+    chrono::prelude::*,
+    rust_decimal::prelude::*,
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::{BTreeMap, HashMap},
+        io::{BufRead, Write},
+        path::PathBuf,
+        sync::Mutex,
+    },
+};
+
+/// Append-only store of price samples, one CSV file of
+/// `timestamp,price,source` lines per token.
+pub struct PriceHistory {
+    dir: PathBuf,
+    last: Mutex<HashMap<String, DateTime<Utc>>>,
+}
+
+impl PriceHistory {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            last: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn path(&self, token: &str) -> PathBuf {
+        self.dir.join(format!("{token}.csv"))
+    }
+
+    /// Appends the sample unless it is not newer than the last one recorded,
+    /// as happens with file and oracle prices that did not change.
+    pub fn record(&self, token: &str, price: &Price) -> Result<(), String> {
+        let mut last = self.last.lock().unwrap();
+        let previous = match last.get(token) {
+            Some(when) => Some(*when),
+            None => self.samples(token, None).last().map(|x| x.0),
+        };
+        if previous.is_some_and(|when| price.when <= when) {
+            return Ok(());
+        }
+        std::fs::create_dir_all(&self.dir)
+            .map_err(|e| format!("Failed to create {}: {:?}", self.dir.display(), e))?;
+        let path = self.path(token);
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| format!("Failed to open {}: {:?}", path.display(), e))?;
+        writeln!(
+            file,
+            "{},{},{}",
+            price.when.to_rfc3339(),
+            price.value,
+            price.source.replace(',', " ")
+        )
+        .map_err(|e| format!("Failed to write {}: {:?}", path.display(), e))?;
+        last.insert(token.to_string(), price.when);
+        Ok(())
+    }
+
+    /// Samples in time order, optionally only those taken at or after `since`.
+    pub fn samples(&self, token: &str, since: Option<DateTime<Utc>>) -> Vec<(DateTime<Utc>, f64)> {
+        let Ok(file) = std::fs::File::open(self.path(token)) else {
+            return vec![];
+        };
+        std::io::BufReader::new(file)
+            .lines()
+            .map_while(Result::ok)
+            .filter_map(|line| {
+                let mut fields = line.split(',');
+                let when = DateTime::parse_from_rfc3339(fields.next()?).ok()?;
+                let value = fields.next()?.parse::<f64>().ok()?;
+                Some((when.with_timezone(&Utc), value))
+            })
+            .filter(|(when, _)| since.is_none_or(|since| *when >= since))
+            .collect()
+    }
+
+    /// The last recorded price, used when no source answers.
+    pub fn last(&self, token: &str) -> Option<Price> {
+        let path = self.path(token);
+        let file = std::fs::File::open(path).ok()?;
+        let line = std::io::BufReader::new(file)
+            .lines()
+            .map_while(Result::ok)
+            .last()?;
+        let mut fields = line.splitn(3, ',');
+        let when = DateTime::parse_from_rfc3339(fields.next()?).ok()?;
+        let value = fields.next()?.parse::<Decimal>().ok()?;
+        Some(Price {
+            value,
+            source: format!("history ({})", fields.next().unwrap_or_default()),
+            when: when.with_timezone(&Utc),
+        })
+    }
+
+    /// The price of the token at the given time, the latest sample taken
+    /// at or before it.
+    pub fn price_at(&self, token: &str, when: DateTime<Utc>) -> Option<f64> {
+        self.samples(token, None)
+            .into_iter()
+            .take_while(|x| x.0 <= when)
+            .last()
+            .map(|x| x.1)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TokenHolding {
+    pub amount: f64,
+    pub value: f64,
+    pub cost_basis: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PortfolioSnapshot {
+    pub when: DateTime<Utc>,
+    pub value: f64,
+    pub cost_basis: f64,
+    pub tokens: BTreeMap<String, TokenHolding>,
+    /// Held tokens left out of the value and cost basis for lack of a price.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unpriced: Vec<String>,
+}
+
+impl PortfolioSnapshot {
+    pub fn unrealized_gain(&self) -> f64 {
+        self.value - self.cost_basis
+    }
+}
+
+/// Portfolio value snapshots of one profile, stored as JSON lines.
+pub struct PortfolioHistory {
+    path: PathBuf,
+}
+
+impl PortfolioHistory {
+    pub fn new(dir: PathBuf, profile: &str) -> Self {
+        Self {
+            path: dir.join(format!("portfolio-{profile}.jsonl")),
+        }
+    }
+
+    pub fn record(&self, snapshot: &PortfolioSnapshot) -> Result<(), String> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create {}: {:?}", dir.display(), e))?;
+        }
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| format!("Failed to open {}: {:?}", self.path.display(), e))?;
+        let line = serde_json::to_string(snapshot).map_err(|e| e.to_string())?;
+        writeln!(file, "{line}")
+            .map_err(|e| format!("Failed to write {}: {:?}", self.path.display(), e))
+    }
+
+    pub fn snapshots(&self, since: Option<DateTime<Utc>>) -> Vec<PortfolioSnapshot> {
+        let Ok(file) = std::fs::File::open(&self.path) else {
+            return vec![];
+        };
+        std::io::BufReader::new(file)
+            .lines()
+            .map_while(Result::ok)
+            .filter_map(|line| serde_json::from_str::<PortfolioSnapshot>(&line).ok())
+            .filter(|x| since.is_none_or(|since| x.when >= since))
+            .collect()
+    }
+
+    pub fn last(&self) -> Option<PortfolioSnapshot> {
+        self.snapshots(None).pop()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("sys-ui-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000 + secs, 0).unwrap()
+    }
+
+    fn price(value: &str, secs: i64) -> Price {
+        Price {
+            value: value.parse().unwrap(),
+            source: "Coinbase, spot".to_string(),
+            when: at(secs),
+        }
+    }
+
+    #[test]
+    fn price_history_round_trip() {
+        let dir = temp_dir("prices");
+        let history = PriceHistory::new(dir.clone());
+        history.record("SOL", &price("142.37", 0)).unwrap();
+        history.record("SOL", &price("143", 60)).unwrap();
+        // not newer than the last sample
+        history.record("SOL", &price("150", 60)).unwrap();
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(dir.join("SOL.csv"))
+            .unwrap();
+        writeln!(file, "not a timestamp,1").unwrap();
+        history.record("SOL", &price("144.5", 120)).unwrap();
+
+        assert_eq!(
+            history.samples("SOL", None),
+            vec![(at(0), 142.37), (at(60), 143.), (at(120), 144.5)]
+        );
+        assert_eq!(history.samples("SOL", Some(at(60))).len(), 2);
+        assert_eq!(history.price_at("SOL", at(90)), Some(143.));
+        assert_eq!(history.price_at("SOL", at(-1)), None);
+        assert_eq!(
+            history.last("SOL"),
+            Some(Price {
+                value: "144.5".parse().unwrap(),
+                source: "history (Coinbase  spot)".to_string(),
+                when: at(120),
+            })
+        );
+        assert!(history.samples("USDC", None).is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn snapshot(secs: i64, value: f64) -> PortfolioSnapshot {
+        PortfolioSnapshot {
+            when: at(secs),
+            value,
+            cost_basis: 100.,
+            tokens: BTreeMap::from([(
+                "SOL".to_string(),
+                TokenHolding {
+                    amount: 1.,
+                    value,
+                    cost_basis: 100.,
+                },
+            )]),
+            unpriced: vec![],
+        }
+    }
+
+    #[test]
+    fn portfolio_history_round_trip() {
+        let dir = temp_dir("portfolio");
+        let history = PortfolioHistory::new(dir.clone(), "default");
+        assert_eq!(history.last(), None);
+        history.record(&snapshot(0, 140.)).unwrap();
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(dir.join("portfolio-default.jsonl"))
+            .unwrap();
+        writeln!(file, "{{\"when\": ").unwrap();
+        let mut unpriced = snapshot(3600, 150.);
+        unpriced.unpriced = vec!["USDC".to_string()];
+        history.record(&unpriced).unwrap();
+
+        assert_eq!(
+            history.snapshots(None),
+            vec![snapshot(0, 140.), unpriced.clone()]
+        );
+        assert_eq!(history.snapshots(Some(at(1))), vec![unpriced.clone()]);
+        assert_eq!(history.last(), Some(unpriced));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
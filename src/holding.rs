@@ -0,0 +1,117 @@
+use chrono::prelude::*;
+
+/// Holding period of a lot. The period starts the day after acquisition and
+/// is long-term when the lot is held more than one year, that is disposed of
+/// after the anniversary of the acquisition date. A lot acquired on February
+/// 29 has its anniversary on February 28 when the next year is not a leap
+/// year.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HoldingPeriod {
+    pub acquired: NaiveDate,
+}
+
+impl HoldingPeriod {
+    pub fn new(acquired: NaiveDate) -> Self {
+        Self { acquired }
+    }
+
+    pub fn anniversary(&self) -> NaiveDate {
+        self.acquired
+            .checked_add_months(Months::new(12))
+            .unwrap_or(NaiveDate::MAX)
+    }
+
+    /// The first day a disposal is long-term.
+    pub fn long_term_date(&self) -> NaiveDate {
+        self.anniversary().succ_opt().unwrap_or(NaiveDate::MAX)
+    }
+
+    pub fn is_long_term(&self, date: NaiveDate) -> bool {
+        date >= self.long_term_date()
+    }
+
+    /// Days from `date` until the lot turns long-term, zero once it has.
+    pub fn days_to_long_term(&self, date: NaiveDate) -> i64 {
+        self.long_term_date()
+            .signed_duration_since(date)
+            .num_days()
+            .max(0)
+    }
+
+    /// "S" or "L", as shown in the term columns.
+    pub fn term(&self, date: NaiveDate) -> &'static str {
+        if self.is_long_term(date) {
+            "L"
+        } else {
+            "S"
+        }
+    }
+
+    /// Tooltip for the term columns.
+    pub fn describe(&self, date: NaiveDate) -> String {
+        let long_term_date = self.long_term_date();
+        if self.is_long_term(date) {
+            format!("long-term since {long_term_date}")
+        } else {
+            format!(
+                "long-term on {long_term_date}, {} days left",
+                self.days_to_long_term(date)
+            )
+        }
+    }
+}
+
+pub fn is_long_term(acquired: NaiveDate, date: NaiveDate) -> bool {
+    HoldingPeriod::new(acquired).is_long_term(date)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn leap_day_anniversary() {
+        let period = HoldingPeriod::new(date(2024, 2, 29));
+        assert_eq!(period.anniversary(), date(2025, 2, 28));
+        assert_eq!(period.long_term_date(), date(2025, 3, 1));
+        assert!(!period.is_long_term(date(2025, 2, 28)));
+        assert!(period.is_long_term(date(2025, 3, 1)));
+    }
+
+    #[test]
+    fn anniversary_is_short_term() {
+        let period = HoldingPeriod::new(date(2023, 6, 15));
+        assert_eq!(period.anniversary(), date(2024, 6, 15));
+        assert!(!period.is_long_term(date(2024, 6, 15)));
+        assert!(period.is_long_term(date(2024, 6, 16)));
+        assert!(!is_long_term(date(2023, 6, 15), date(2024, 6, 15)));
+        assert!(is_long_term(date(2023, 6, 15), date(2024, 6, 16)));
+    }
+
+    #[test]
+    fn days_to_long_term() {
+        let period = HoldingPeriod::new(date(2023, 6, 15));
+        assert_eq!(period.days_to_long_term(date(2024, 6, 15)), 1);
+        assert_eq!(period.days_to_long_term(date(2024, 6, 16)), 0);
+        assert_eq!(period.days_to_long_term(date(2025, 1, 1)), 0);
+    }
+
+    #[test]
+    fn term_and_describe() {
+        let period = HoldingPeriod::new(date(2023, 6, 15));
+        assert_eq!(period.term(date(2024, 6, 15)), "S");
+        assert_eq!(period.term(date(2024, 6, 16)), "L");
+        assert_eq!(
+            period.describe(date(2024, 6, 5)),
+            "long-term on 2024-06-16, 11 days left"
+        );
+        assert_eq!(
+            period.describe(date(2024, 7, 1)),
+            "long-term since 2024-06-16"
+        );
+    }
+}
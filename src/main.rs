@@ -1,6 +1,15 @@
+mod history;
+mod holding;
+mod price;
+mod table;
+mod tax;
+
 use {
     chrono::prelude::*,
     dioxus::prelude::*,
+    history::*,
+    holding::HoldingPeriod,
+    price::*,
     rust_decimal::prelude::*,
     separator::FixedPlaceSeparatable,
     serde::{Deserialize, Serialize},
@@ -8,12 +17,13 @@ use {
     solana_client::rpc_client::RpcClient,
     solana_pubkey::Pubkey,
     solana_sdk::{
-        account_utils::StateMut, message::Message, signers::Signers, system_program,
-        transaction::Transaction,
+        account_utils::StateMut, instruction::Instruction, message::Message, signature::Keypair,
+        signer::Signer, signers::Signers, system_program, transaction::Transaction,
     },
     std::{
         collections::{BTreeMap, BTreeSet, HashMap, HashSet},
         io::Write,
+        sync::Arc,
     },
     sys::{
         db::{self, *},
@@ -24,57 +34,33 @@ use {
         token::*,
         RpcClients,
     },
+    table::*,
 };
 
 const FAVICON: Asset = asset!("/assets/favicon.ico");
 const MAIN_CSS: Asset = asset!("/assets/main.css");
 lazy_static::lazy_static! {
-    static ref CONFIG: Config = {
-        let home = std::env::var("HOME").unwrap_or_default();
-        let home = std::path::PathBuf::from(home);
-        let conf = home.join(".config").join("sys-ui").join("config.yml");
-        let conf_clone = conf.clone();
-        let file = std::fs::File::open(conf).unwrap_or_else(|e| {
-            eprintln!(
-                "Failed to open config file {}: {:?}",
-                conf_clone.display(),
-                e
-            );
-            std::process::exit(1);
-        });
-        serde_yaml::from_reader(file).unwrap_or_else(|e| {
-            eprintln!(
-                "Failed to read config from file {}: {:?}",
-                conf_clone.display(),
-                e
-            );
-            std::process::exit(1);
-        })
-    };
+    static ref CONFIG: Config = Config::load().unwrap_or_else(|err| {
+        eprintln!("{err}");
+        std::process::exit(1);
+    });
 
     static ref DB: std::sync::Arc<std::sync::RwLock<Db>> = {
-        let db_path = std::path::PathBuf::from(CONFIG.db_path.clone());
-        let mut db_fd_lock = fd_lock::RwLock::new(std::fs::File::open(&db_path).unwrap());
-        let _db_write_lock = loop {
-            match db_fd_lock.try_write() {
-                Ok(lock) => break lock,
-                Err(err) => {
-                    eprintln!(
-                        "Unable to lock database directory: {}: {}",
-                        db_path.display(),
-                        err
-                    );
-                    std::thread::sleep(std::time::Duration::from_secs(1));
-                }
-            }
-        };
-        let db = db::new(&db_path).unwrap_or_else(|err| {
-            eprintln!("Failed to open {}: {}", db_path.display(), err);
+        let (db, holder) = open_db(&CONFIG.db_path).unwrap_or_else(|err| {
+            eprintln!("{err}");
             std::process::exit(1)
         });
+        *DB_READ_ONLY.write().unwrap() = holder;
         std::sync::Arc::new(std::sync::RwLock::new(db))
     };
 
+    static ref DB_LOCK: std::sync::Mutex<Option<fd_lock::RwLock<std::fs::File>>> =
+        std::sync::Mutex::new(None);
+
+    static ref DB_READ_ONLY: std::sync::RwLock<Option<String>> = std::sync::RwLock::new(None);
+
+    static ref PRICE_HISTORY: PriceHistory = PriceHistory::new(CONFIG.price_history_dir());
+
     static ref RPC: std::sync::Arc<std::sync::RwLock<RpcClients>> =
         std::sync::Arc::new(
             std::sync::RwLock::new(
@@ -82,37 +68,502 @@ lazy_static::lazy_static! {
 
 }
 
+const DEFAULT_PROFILE: &str = "default";
+
 #[derive(Serialize, Deserialize, Debug)]
 struct Config {
     pub db_path: String,
     pub json_rpc_url: String,
     pub authority_keypair: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, Profile>,
+    #[serde(default = "default_price_sources")]
+    pub price_sources: Vec<PriceSource>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub price_history: Option<String>,
+    #[serde(default, skip_serializing_if = "ExtraTaxRates::is_empty")]
+    pub extra_tax_rates: ExtraTaxRates,
 }
 
-#[derive(Clone)]
-enum Sorting {
-    Lot(bool),
-    Date(bool),
-    Amount(bool),
-    Price(bool),
+/// Rates added on top of the federal rates stored in the sys db.
+#[derive(Clone, Copy, Default, Serialize, Deserialize, Debug, PartialEq)]
+struct ExtraTaxRates {
+    pub state: Option<f64>,
+    pub niit: Option<f64>,
 }
 
-#[derive(Clone)]
-enum DisposedSorting {
-    Lot(bool),
-    SaleDate(bool),
-    AcqDate(bool),
-    Amount(bool),
+impl ExtraTaxRates {
+    fn is_empty(&self) -> bool {
+        *self == ExtraTaxRates::default()
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+struct Profile {
+    pub db_path: String,
+    pub json_rpc_url: String,
+    pub authority_keypair: Option<String>,
+}
+
+impl Config {
+    fn path() -> std::path::PathBuf {
+        let home = std::env::var("HOME").unwrap_or_default();
+        let home = std::path::PathBuf::from(home);
+        home.join(".config").join("sys-ui").join("config.yml")
+    }
+
+    fn load() -> Result<Config, String> {
+        let conf = Config::path();
+        let file = std::fs::File::open(&conf)
+            .map_err(|e| format!("Failed to open config file {}: {:?}", conf.display(), e))?;
+        serde_yaml::from_reader(file).map_err(|e| {
+            format!(
+                "Failed to read config from file {}: {:?}",
+                conf.display(),
+                e
+            )
+        })
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let conf = Config::path();
+        if let Some(dir) = conf.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create {}: {:?}", dir.display(), e))?;
+        }
+        let file = std::fs::File::create(&conf)
+            .map_err(|e| format!("Failed to create config file {}: {:?}", conf.display(), e))?;
+        serde_yaml::to_writer(file, self)
+            .map_err(|e| format!("Failed to write config to file {}: {:?}", conf.display(), e))
+    }
+
+    /// The top level settings make up the default profile, the named
+    /// profiles fall back to the default authority keypair.
+    fn profile(&self, name: &str) -> Option<Profile> {
+        if name == DEFAULT_PROFILE {
+            return Some(Profile {
+                db_path: self.db_path.clone(),
+                json_rpc_url: self.json_rpc_url.clone(),
+                authority_keypair: Some(self.authority_keypair.clone()),
+            });
+        }
+        self.profiles.get(name).map(|profile| Profile {
+            authority_keypair: profile
+                .authority_keypair
+                .clone()
+                .or_else(|| Some(self.authority_keypair.clone())),
+            ..profile.clone()
+        })
+    }
+
+    fn price_history_dir(&self) -> std::path::PathBuf {
+        match self.price_history {
+            Some(ref dir) => std::path::PathBuf::from(dir),
+            None => Config::path().with_file_name("prices"),
+        }
+    }
+
+    fn profile_names(&self) -> Vec<String> {
+        let mut names = vec![DEFAULT_PROFILE.to_string()];
+        names.extend(self.profiles.keys().cloned());
+        names
+    }
+}
+
+/// Takes the write lock on the database directory and keeps it until the
+/// returned lock is dropped. On failure returns who holds the lock.
+fn lock_db(db_path: &std::path::Path) -> Result<fd_lock::RwLock<std::fs::File>, String> {
+    let file = std::fs::File::open(db_path)
+        .map_err(|err| format!("unable to open {}: {}", db_path.display(), err))?;
+    let mut db_fd_lock = fd_lock::RwLock::new(file);
+    match db_fd_lock.try_write() {
+        Ok(lock) => std::mem::forget(lock),
+        Err(_) => return Err(db_lock_holder(db_path)),
+    }
+    Ok(db_fd_lock)
+}
+
+#[cfg(target_os = "linux")]
+fn db_lock_holder(db_path: &std::path::Path) -> String {
+    use std::os::unix::fs::MetadataExt;
+    let inode = std::fs::metadata(db_path)
+        .map(|x| x.ino().to_string())
+        .unwrap_or_default();
+    let locks = std::fs::read_to_string("/proc/locks").unwrap_or_default();
+    for line in locks.lines().filter(|x| !x.contains("->")) {
+        // 1: FLOCK  ADVISORY  WRITE 1234 08:01:5678 0 EOF
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        if fields.len() > 5 && fields[5].rsplit(':').next() == Some(inode.as_str()) {
+            let pid = fields[4];
+            let command = std::fs::read_to_string(format!("/proc/{pid}/cmdline"))
+                .unwrap_or_default()
+                .replace('\0', " ");
+            return format!("process {pid} {}", command.trim());
+        }
+    }
+    "another process".to_string()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn db_lock_holder(_db_path: &std::path::Path) -> String {
+    "another process".to_string()
+}
+
+/// Copies the database files to a private directory next to the config
+/// file. sys has no read-only mode, so a database locked by another
+/// process is opened from the copy and nothing written here reaches it.
+fn read_only_copy(db_path: &std::path::Path) -> Result<std::path::PathBuf, String> {
+    let copy = Config::path().with_file_name("read-only");
+    let _ = std::fs::remove_dir_all(&copy);
+    let mut builder = std::fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder
+        .create(&copy)
+        .map_err(|err| format!("unable to create {}: {}", copy.display(), err))?;
+    let entries = std::fs::read_dir(db_path)
+        .map_err(|err| format!("unable to read {}: {}", db_path.display(), err))?;
+    for entry in entries.filter_map(Result::ok) {
+        if entry.file_type().is_ok_and(|x| x.is_file()) {
+            std::fs::copy(entry.path(), copy.join(entry.file_name()))
+                .map_err(|err| format!("unable to copy {}: {}", entry.path().display(), err))?;
+        }
+    }
+    Ok(copy)
+}
+
+/// Opens the database read-write when its lock can be taken, otherwise
+/// read-only, in which case the second value says who holds the lock.
+fn open_db(db_path: &str) -> Result<(Db, Option<String>), String> {
+    let db_path = std::path::PathBuf::from(db_path);
+    match lock_db(&db_path) {
+        Ok(lock) => {
+            let db = db::new(&db_path)
+                .map_err(|err| format!("Failed to open {}: {}", db_path.display(), err))?;
+            *DB_LOCK.lock().unwrap() = Some(lock);
+            let _ = std::fs::remove_dir_all(Config::path().with_file_name("read-only"));
+            Ok((db, None))
+        }
+        Err(holder) => {
+            eprintln!(
+                "Unable to lock database directory {}, held by {}, opening read-only",
+                db_path.display(),
+                holder
+            );
+            let copy = read_only_copy(&db_path)?;
+            let db = db::new(&copy)
+                .map_err(|err| format!("Failed to open {}: {}", db_path.display(), err))?;
+            *DB_LOCK.lock().unwrap() = None;
+            Ok((db, Some(holder)))
+        }
+    }
+}
+
+#[derive(Clone, Default, PartialEq)]
+struct DisposedFilter {
+    pub year: Option<i32>,
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    pub token: Option<String>,
+    pub long_term: Option<bool>,
+    pub kind: Option<String>,
+}
+
+impl DisposedFilter {
+    fn matches(&self, lot: &DisposedLot) -> bool {
+        self.year.is_none_or(|x| lot.when.year() == x)
+            && self.from.is_none_or(|x| lot.when >= x)
+            && self.to.is_none_or(|x| lot.when <= x)
+            && self
+                .token
+                .as_ref()
+                .is_none_or(|x| *x == lot.token.to_string())
+            && self
+                .long_term
+                .is_none_or(|x| x == holding::is_long_term(lot.lot.acquisition.when, lot.when))
+            && self
+                .kind
+                .as_ref()
+                .is_none_or(|x| *x == acquisition_kind_name(&lot.lot.acquisition.kind))
+    }
+}
+
+/// The variant name of the acquisition kind, such as `EpochReward`.
+fn acquisition_kind_name(kind: &LotAcquistionKind) -> String {
+    format!("{kind:?}")
+        .split(|c: char| !c.is_alphanumeric())
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn get_wash_sales(db: &Db) -> BTreeMap<usize, tax::WashSale> {
+    let held = db
+        .get_accounts()
+        .into_iter()
+        .flat_map(|account| {
+            account
+                .lots
+                .into_iter()
+                .map(move |lot| (account.token, lot))
+        })
+        .collect::<Vec<_>>();
+    let disposed = db.disposed_lots();
+    let sales = disposed.iter().map(tax::Sale::from).collect::<Vec<_>>();
+    tax::wash_sales(&sales, &held)
+}
+
+fn filtered_disposed_lots(filter: &DisposedFilter) -> Vec<DisposedLot> {
+    DB.read()
+        .unwrap()
+        .disposed_lots()
+        .into_iter()
+        .filter(|x| filter.matches(x))
+        .collect()
 }
 
 #[derive(Clone, Props)]
 struct State {
-    pub sorted: Option<Sorting>,
     pub amount: Option<f64>,
     pub authority: Option<String>,
     pub recipient: Option<String>,
     pub url: Option<String>,
-    pub disposed_sorted: Option<DisposedSorting>,
+    pub disposed_filter: DisposedFilter,
+    pub dry_run: bool,
+    pub harvest: bool,
+    pub profile: String,
+    pub seed: Option<String>,
+    pub custodian: Option<String>,
+    pub authorization: Authorization,
+    pub lot_method: LotMethod,
+    pub lockup_date: Option<String>,
+    pub lockup_epoch: Option<String>,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+enum LotMethod {
+    #[default]
+    Fifo,
+    Lifo,
+    Hifo,
+    Lofo,
+    SpecificId,
+}
+
+impl LotMethod {
+    const ALL: [LotMethod; 5] = [
+        LotMethod::Fifo,
+        LotMethod::Lifo,
+        LotMethod::Hifo,
+        LotMethod::Lofo,
+        LotMethod::SpecificId,
+    ];
+
+    /// Specific ID consumes only the selected lots, whatever the method.
+    fn lot_selection_method(&self) -> LotSelectionMethod {
+        match self {
+            LotMethod::Fifo | LotMethod::SpecificId => LotSelectionMethod::FirstInFirstOut,
+            LotMethod::Lifo => LotSelectionMethod::LastInFirstOut,
+            LotMethod::Hifo => LotSelectionMethod::HighestBasis,
+            LotMethod::Lofo => LotSelectionMethod::LowestBasis,
+        }
+    }
+
+    fn sort(&self, lots: &mut [Lot]) {
+        match self {
+            LotMethod::Fifo | LotMethod::SpecificId => lots.sort_by_key(|x| x.acquisition.when),
+            LotMethod::Lifo => lots.sort_by(|a, b| b.acquisition.when.cmp(&a.acquisition.when)),
+            LotMethod::Hifo => {
+                lots.sort_by(|a, b| b.acquisition.price().cmp(&a.acquisition.price()))
+            }
+            LotMethod::Lofo => lots.sort_by_key(|x| x.acquisition.price()),
+        }
+    }
+}
+
+impl std::fmt::Display for LotMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            LotMethod::Fifo => "FIFO",
+            LotMethod::Lifo => "LIFO",
+            LotMethod::Hifo => "HIFO",
+            LotMethod::Lofo => "LOFO",
+            LotMethod::SpecificId => "specific ID",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// The lots an amount is taken from and how much of each, in the order of
+/// the method: the selected lots, or any lots when none are selected, as
+/// sys does with the lot numbers it's given.
+fn select_lots(
+    lots: &[Lot],
+    selected: &BTreeSet<usize>,
+    amount: u64,
+    method: LotMethod,
+) -> Result<Vec<(Lot, u64)>, String> {
+    if method == LotMethod::SpecificId && selected.is_empty() {
+        return Err("Select the lots to use with specific ID".to_string());
+    }
+    let mut lots = lots
+        .iter()
+        .filter(|x| selected.is_empty() || selected.contains(&x.lot_number))
+        .cloned()
+        .collect::<Vec<_>>();
+    method.sort(&mut lots);
+    let mut remaining = amount;
+    let mut used = vec![];
+    for lot in lots {
+        if remaining == 0 {
+            break;
+        }
+        let take = remaining.min(lot.amount);
+        remaining -= take;
+        used.push((lot, take));
+    }
+    if remaining > 0 {
+        return Err(format!("The lots are short by {remaining} base units"));
+    }
+    Ok(used)
+}
+
+/// The selected lots that belong to the account, lots of other accounts
+/// left selected don't restrict the lots an operation on it uses.
+fn account_selection(account: &TrackedAccount, selected: &BTreeSet<usize>) -> BTreeSet<usize> {
+    account
+        .lots
+        .iter()
+        .map(|x| x.lot_number)
+        .filter(|x| selected.contains(x))
+        .collect()
+}
+
+fn describe_lots(token: MaybeToken, used: &[(Lot, u64)]) -> Vec<String> {
+    used.iter()
+        .map(|(lot, amount)| {
+            if *amount == lot.amount {
+                format!("{} ({})", lot.lot_number, token.format_amount(*amount))
+            } else {
+                format!(
+                    "{} ({} of {})",
+                    lot.lot_number,
+                    token.format_amount(*amount),
+                    token.format_amount(lot.amount)
+                )
+            }
+        })
+        .collect()
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Authorization {
+    Staker,
+    Withdrawer,
+    Both,
+}
+
+impl std::fmt::Display for Authorization {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            Authorization::Staker => "staker",
+            Authorization::Withdrawer => "withdrawer",
+            Authorization::Both => "staker and withdrawer",
+        };
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Operation {
+    Split,
+    Deactivate,
+    Withdraw,
+    Delegate,
+    Swap,
+    Merge,
+    NewStake,
+    Authorize,
+    SetLockup,
+    MoveStake,
+    MoveLamports,
+}
+
+impl std::fmt::Display for Operation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            Operation::Split => "split",
+            Operation::Deactivate => "deactivate",
+            Operation::Withdraw => "withdraw",
+            Operation::Delegate => "delegate",
+            Operation::Swap => "swap",
+            Operation::Merge => "merge",
+            Operation::NewStake => "create stake account",
+            Operation::Authorize => "authorize",
+            Operation::SetLockup => "set lockup",
+            Operation::MoveStake => "move stake",
+            Operation::MoveLamports => "move lamports",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// An operation as previewed. Confirming runs it with the account, inputs,
+/// amount and lots captured here rather than with the current inputs.
+#[derive(Clone)]
+struct Preview {
+    pub operation: Operation,
+    pub account: TrackedAccount,
+    pub state: State,
+    /// The selected lots of the account.
+    pub selected: BTreeSet<usize>,
+    pub fee_payer: Pubkey,
+    pub recipient: Option<String>,
+    pub amount: u64,
+    pub lots: Vec<String>,
+    /// Keypair of the account the operation creates, generated once so the
+    /// address previewed is the address funded.
+    pub new_account: Option<Arc<Keypair>>,
+    pub instructions: Vec<String>,
+    pub fee: Option<u64>,
+    pub simulation: String,
+}
+
+impl Preview {
+    /// The selected lots of the account, or none to let the lot method pick
+    /// the lots for the amount.
+    fn lot_numbers(&self) -> Option<HashSet<usize>> {
+        if self.selected.is_empty() {
+            None
+        } else {
+            Some(self.selected.iter().copied().collect())
+        }
+    }
+
+    fn report(&self) -> String {
+        let fee = self
+            .fee
+            .map(|x| MaybeToken::SOL().format_amount(x))
+            .unwrap_or_else(|| "unknown".to_string());
+        let lots = self.lots.join(", ");
+        let mut report = format!(
+            "Account:      {} ({})\nFee payer:    {}\n",
+            self.account.address, self.account.token, self.fee_payer
+        );
+        if let Some(ref recipient) = self.recipient {
+            report = format!("{report}Recipient:    {recipient}\n");
+        }
+        report = format!(
+            "{report}Amount:       {}\nLots ({}): {lots}\nEstimated fee: {fee}\nInstructions:\n",
+            self.account.token.format_amount(self.amount),
+            self.state.lot_method
+        );
+        for (i, instruction) in self.instructions.iter().enumerate() {
+            report = format!("{report}  {}. {instruction}\n", i + 1);
+        }
+        format!("{report}Simulation:\n{}", self.simulation)
+    }
 }
 
 impl PartialEq for State {
@@ -124,16 +575,36 @@ impl PartialEq for State {
 #[derive(Clone, Copy)]
 struct GlobalState {
     state: Signal<State>,
-    prices: Signal<BTreeMap<String, f64>>,
+    prices: Signal<BTreeMap<String, Price>>,
     account: Signal<Option<TrackedAccount>>,
     selected: Signal<BTreeSet<usize>>,
     disposed_selected: Signal<BTreeSet<usize>>,
+    lots_table: Signal<TableState>,
+    disposed_table: Signal<TableState>,
     xaccount: Signal<Option<(Exchange, String)>>,
     xpmethod: Signal<Option<(Exchange, String)>>,
-    xclients: Signal<Option<HashMap<Exchange, Box<dyn ExchangeClient>>>>,
+    xclients: Signal<Option<Arc<ExchangeClients>>>,
     xupdate: Signal<bool>,
     reload: Signal<bool>,
     log: Signal<Option<String>>,
+    pending: Signal<Option<Preview>>,
+    read_only: Signal<Option<String>>,
+    extra_tax_rates: Signal<ExtraTaxRates>,
+}
+
+type ExchangeClients = HashMap<Exchange, Box<dyn ExchangeClient>>;
+
+/// Clients of the exchanges with credentials in the sys db.
+fn exchange_clients(db: &Db) -> ExchangeClients {
+    let mut xclients = HashMap::<_, _>::new();
+    for x in db.get_exchanges() {
+        if let Some(credentials) = db.get_exchange_credentials(x, &"") {
+            if let Ok(client) = exchange_client_new(x, credentials) {
+                xclients.insert(x, client);
+            }
+        }
+    }
+    xclients
 }
 
 #[derive(Routable, Clone)]
@@ -143,6 +614,14 @@ enum Route {
     Main {},
     #[route("/disposed")]
     Disposed {},
+    #[route("/validators")]
+    Validators {},
+    #[route("/history")]
+    History {},
+    #[route("/simulator")]
+    Simulator {},
+    #[route("/settings")]
+    Settings {},
     #[end_layout]
     #[route("/:..route")]
     PageNotFound { route: Vec<String> },
@@ -151,72 +630,174 @@ enum Route {
 enum Action {}
 
 fn main() {
-    dioxus::launch(App);
+    if Config::load().is_ok() {
+        dioxus::launch(App);
+    } else {
+        dioxus::launch(Setup);
+    }
 }
 
 #[component]
 fn App() -> Element {
-    let db = DB.read().unwrap();
-    let exchanges = db.get_exchanges();
-    let mut xclients = HashMap::<_, _>::new();
-    for x in exchanges {
-        if let Some(credentials) = db.get_exchange_credentials(x, &"") {
-            if let Ok(client) = exchange_client_new(x, credentials) {
-                xclients.insert(x, client);
-            }
-        }
-    }
+    let xclients = exchange_clients(&DB.read().unwrap());
     let selected = use_signal(|| BTreeSet::default());
     let disposed_selected = use_signal(|| BTreeSet::default());
     let log = use_signal(|| None);
     let _global_state = use_context_provider(|| GlobalState {
         state: Signal::new(State {
-            sorted: None,
             amount: None,
             authority: Some(CONFIG.authority_keypair.clone()),
             recipient: None,
             url: Some(CONFIG.json_rpc_url.clone()),
-            disposed_sorted: None,
+            disposed_filter: DisposedFilter::default(),
+            dry_run: false,
+            harvest: false,
+            profile: DEFAULT_PROFILE.to_string(),
+            seed: None,
+            custodian: None,
+            authorization: Authorization::Staker,
+            lot_method: LotMethod::default(),
+            lockup_date: None,
+            lockup_epoch: None,
         }),
         prices: Signal::new(BTreeMap::default()),
         account: Signal::new(None),
         selected,
         disposed_selected,
+        lots_table: Signal::new(TableState::default()),
+        disposed_table: Signal::new(TableState::default()),
         xaccount: Signal::new(None),
         xpmethod: Signal::new(None),
-        xclients: Signal::new(Some(xclients)),
+        xclients: Signal::new(Some(Arc::new(xclients))),
         xupdate: Signal::new(false),
         reload: Signal::new(false),
         log,
+        pending: Signal::new(None),
+        read_only: Signal::new(DB_READ_ONLY.read().unwrap().clone()),
+        extra_tax_rates: Signal::new(CONFIG.extra_tax_rates),
     });
 
     let mut prices = use_context::<GlobalState>().prices;
     let xclients = use_context::<GlobalState>().xclients;
+    let mut log = use_context::<GlobalState>().log;
     use_coroutine(move |_rx: UnboundedReceiver<Action>| async move {
-        let mut tokens = vec![MaybeToken::from(None)];
-        tokens.append(
-            &mut Token::VARIANTS
-                .into_iter()
-                .map(|x| MaybeToken::from(Some(x)))
-                .collect::<Vec<MaybeToken>>(),
-        );
+        let tokens = price_tokens();
+        let mut history_errors = HashMap::new();
         loop {
-            let rpc = RPC.read().unwrap();
-            let xclients = xclients.read();
-            let exchange_client = xclients.as_ref().unwrap().get(&Exchange::Coinbase).unwrap();
+            // The lock isn't held across the lookups, switching profiles
+            // takes it for writing.
+            let rpc_client = RpcClient::new(RPC.read().unwrap().default().url());
+            // nor is the signal borrowed, switching profiles replaces the
+            // clients
+            let xclients = xclients.read().clone();
             for token in tokens.iter() {
-                let price = token
-                    .get_spot_price(exchange_client.as_ref(), None)
-                    .await
-                    .map(|x| format!("{x:.6}").trim().parse::<f64>().unwrap())
-                    .unwrap_or(0f64);
-                prices.write().insert(token.to_string(), price);
-            }
-            drop(rpc);
+                // A failed lookup keeps the last known price, which the
+                // Tokens panel then shows as stale.
+                match get_price(
+                    &CONFIG.price_sources,
+                    token,
+                    &rpc_client,
+                    xclients.as_ref().unwrap(),
+                )
+                .await
+                {
+                    Ok(price) => {
+                        // reported once per token rather than on every lookup
+                        match PRICE_HISTORY.record(&token.to_string(), &price) {
+                            Ok(()) => {
+                                history_errors.remove(&token.to_string());
+                            }
+                            Err(e) if history_errors.get(&token.to_string()) != Some(&e) => {
+                                log.set(Some(format!("Failed to record price history: {e}")));
+                                history_errors.insert(token.to_string(), e);
+                            }
+                            Err(_) => (),
+                        }
+                        prices.write().insert(token.to_string(), price);
+                    }
+                    Err(_) => {
+                        if !prices.read().contains_key(&token.to_string()) {
+                            if let Some(price) = PRICE_HISTORY.last(&token.to_string()) {
+                                prices.write().insert(token.to_string(), price);
+                            }
+                        }
+                    }
+                }
+            }
+            // Touched even when every lookup failed so that prices turn
+            // stale on screen.
+            let _ = prices.write();
             tokio::time::sleep(std::time::Duration::from_secs(30)).await;
         }
     });
 
+    let mut read_only = use_context::<GlobalState>().read_only;
+    let state = use_context::<GlobalState>().state;
+    use_future(move || async move {
+        loop {
+            tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+            if read_only.read().is_none() {
+                continue;
+            }
+            let profile = state.read().profile.clone();
+            let db_path = CONFIG.profile(&profile).unwrap().db_path;
+            let db_path = std::path::PathBuf::from(db_path);
+            match lock_db(&db_path) {
+                Ok(lock) => match db::new(&db_path) {
+                    Ok(db) => {
+                        *DB.write().unwrap() = db;
+                        *DB_LOCK.lock().unwrap() = Some(lock);
+                        let _ = std::fs::remove_dir_all(Config::path().with_file_name("read-only"));
+                        read_only.set(None);
+                        consume_context::<GlobalState>().reload.set(true);
+                        consume_context::<GlobalState>().log.set(Some(format!(
+                            "Locked database {}, signing is enabled",
+                            db_path.display()
+                        )));
+                    }
+                    Err(e) => consume_context::<GlobalState>().log.set(Some(format!(
+                        "Failed to reopen {}: {}",
+                        db_path.display(),
+                        e
+                    ))),
+                },
+                Err(holder) => {
+                    if read_only.read().as_ref() != Some(&holder) {
+                        read_only.set(Some(holder));
+                    }
+                }
+            }
+        }
+    });
+
+    use_future(move || async move {
+        let mut last_error = None;
+        loop {
+            tokio::time::sleep(std::time::Duration::from_secs(60)).await;
+            let portfolio =
+                PortfolioHistory::new(CONFIG.price_history_dir(), &state.read().profile);
+            let due = portfolio.last().is_none_or(|x| {
+                Utc::now().signed_duration_since(x.when).num_seconds() >= SNAPSHOT_INTERVAL_SECS
+            });
+            if !due {
+                continue;
+            }
+            let snapshot = portfolio_snapshot(&DB.read().unwrap(), &prices.read());
+            let result = snapshot.and_then(|x| portfolio.record(&x));
+            // reported once rather than on every retry
+            match result {
+                Ok(()) => last_error = None,
+                Err(e) if last_error.as_ref() != Some(&e) => {
+                    consume_context::<GlobalState>()
+                        .log
+                        .set(Some(format!("Portfolio snapshot failed: {e}")));
+                    last_error = Some(e);
+                }
+                Err(_) => (),
+            }
+        }
+    });
+
     rsx! {
         document::Link { rel: "icon", href: FAVICON }
         document::Link { rel: "stylesheet", href: MAIN_CSS }
@@ -224,54 +805,357 @@ fn App() -> Element {
     }
 }
 
-#[component]
-fn NavBar() -> Element {
-    rsx! {
-        nav {
-            Link { to: Route::Main {}, "Holdings" }
-            Link { to: Route::Disposed {}, "Disposed" }
+fn check_db_path(db_path: &str, create: bool) -> Result<String, String> {
+    if db_path.is_empty() {
+        return Err("Enter the sys database path".to_string());
+    }
+    let path = std::path::PathBuf::from(db_path);
+    if !path.exists() {
+        if !create {
+            return Err(format!("{db_path} does not exist"));
         }
-        Outlet::<Route> {}
+        std::fs::create_dir_all(&path).map_err(|e| format!("Failed to create {db_path}: {e:?}"))?;
+    }
+    if !path.is_dir() {
+        return Err(format!("{db_path} is not a directory"));
     }
+    let db = db::new(&path).map_err(|e| format!("Failed to open {db_path}: {e}"))?;
+    Ok(format!("{} accounts", db.get_accounts().len()))
+}
+
+fn check_json_rpc_url(json_rpc_url: &str) -> Result<String, String> {
+    if json_rpc_url.is_empty() {
+        return Err("Enter the json rpc url".to_string());
+    }
+    RpcClient::new(json_rpc_url.to_string())
+        .get_version()
+        .map(|version| format!("solana-core {}", version.solana_core))
+        .map_err(|e| format!("Failed to connect to {json_rpc_url}: {e}"))
+}
+
+fn check_authority_keypair(authority_keypair: &str) -> Result<String, String> {
+    if authority_keypair.is_empty() {
+        return Err("Enter the authority keypair".to_string());
+    }
+    signer_pubkey(authority_keypair).map(|pubkey| pubkey.to_string())
 }
 
 #[component]
-pub fn Main() -> Element {
-    rsx! {
-        Menu {}
-        div { id: "sys",
-            Accounts {}
-            Lots {}
-            Tokens {}
-        }
-        Input {}
-        Summary {}
-        Log {}
+fn StatusMessage(status: Option<Result<String, String>>) -> Element {
+    match status {
+        Some(Ok(message)) => rsx! {
+            span { class: "valid", "{message}" }
+        },
+        Some(Err(message)) => rsx! {
+            span { class: "invalid", "{message}" }
+        },
+        None => rsx! {},
     }
 }
 
 #[component]
-pub fn Menu() -> Element {
-    let account = use_context::<GlobalState>().account.read().clone();
-    let address = account.map(|x| x.address);
-    let sync = move |_| {
-        spawn(async move {
-            let mut buffer = std::io::BufWriter::new(Vec::new());
-            let xclients = use_context::<GlobalState>().xclients;
-            let xclients = xclients.read();
-            let exchange_client = xclients.as_ref().unwrap().get(&Exchange::Coinbase).unwrap();
-            match process_account_sync(
-                &mut DB.write().unwrap(),
-                &RPC.read().unwrap(),
-                exchange_client.as_ref(),
-                address,
-                None,
-                false,
-                false,
-                &Notifier::default(),
-                &mut buffer,
-            )
-            .await
+fn Setup() -> Element {
+    let mut done = use_signal(|| false);
+    let problem = use_signal(|| Config::load().err());
+    let mut db_path = use_signal(|| {
+        let home = std::env::var("HOME").unwrap_or_default();
+        std::path::PathBuf::from(home)
+            .join(".sys")
+            .display()
+            .to_string()
+    });
+    let mut json_rpc_url = use_signal(|| "https://api.mainnet-beta.solana.com".to_string());
+    let mut authority_keypair = use_signal(|| "usb://ledger".to_string());
+    let mut db_status = use_signal(|| None);
+    let mut url_status = use_signal(|| None);
+    let mut authority_status = use_signal(|| None);
+    let mut save_status = use_signal(|| None);
+
+    if done() {
+        return rsx! {
+            App {}
+        };
+    }
+    let save = move |_| {
+        let statuses = (
+            check_db_path(&db_path.read(), false),
+            check_json_rpc_url(&json_rpc_url.read()),
+            check_authority_keypair(&authority_keypair.read()),
+        );
+        let valid = statuses.0.is_ok() && statuses.1.is_ok() && statuses.2.is_ok();
+        db_status.set(Some(statuses.0));
+        url_status.set(Some(statuses.1));
+        authority_status.set(Some(statuses.2));
+        if !valid {
+            save_status.set(Some(
+                Err("Fix the settings above before saving".to_string()),
+            ));
+            return;
+        }
+        let config = Config {
+            db_path: db_path.read().clone(),
+            json_rpc_url: json_rpc_url.read().clone(),
+            authority_keypair: authority_keypair.read().clone(),
+            profiles: BTreeMap::default(),
+            price_sources: default_price_sources(),
+            price_history: None,
+            extra_tax_rates: ExtraTaxRates::default(),
+        };
+        // a config that fails to parse may still hold profiles and rates
+        let conf = Config::path();
+        if conf.exists() {
+            let backup = conf.with_extension("yml.bak");
+            if let Err(e) = std::fs::rename(&conf, &backup) {
+                save_status.set(Some(Err(format!(
+                    "Failed to back up {} to {}: {:?}",
+                    conf.display(),
+                    backup.display(),
+                    e
+                ))));
+                return;
+            }
+        }
+        match config.save() {
+            Ok(()) => done.set(true),
+            Err(e) => save_status.set(Some(Err(e))),
+        }
+    };
+    let config_path = Config::path().display().to_string();
+    let backup_path = Config::path().exists().then(|| {
+        Config::path()
+            .with_extension("yml.bak")
+            .display()
+            .to_string()
+    });
+    rsx! {
+        document::Link { rel: "icon", href: FAVICON }
+        document::Link { rel: "stylesheet", href: MAIN_CSS }
+        div { id: "setup",
+            h3 { "sys-ui setup" }
+            if let Some(problem) = problem() {
+                pre { "{problem}" }
+            }
+            p { "The settings are saved to {config_path}" }
+            if let Some(backup_path) = backup_path {
+                p { "Saving moves the current file to {backup_path}, copy anything you need from it back afterwards" }
+            }
+            div {
+                label { r#for: "db_path", "sys database path:" }
+                input {
+                    id: "db_path",
+                    name: "db_path",
+                    value: "{db_path}",
+                    oninput: move |event| {
+                        db_path.set(event.value());
+                        db_status.set(None);
+                    },
+                }
+                button { onclick: move |_| db_status.set(Some(check_db_path(&db_path.read(), false))),
+                    "Check"
+                }
+                button { onclick: move |_| db_status.set(Some(check_db_path(&db_path.read(), true))),
+                    "Create"
+                }
+                StatusMessage { status: db_status() }
+            }
+            div {
+                label { r#for: "json_rpc_url", "json rpc url:" }
+                input {
+                    id: "json_rpc_url",
+                    name: "json_rpc_url",
+                    value: "{json_rpc_url}",
+                    oninput: move |event| {
+                        json_rpc_url.set(event.value());
+                        url_status.set(None);
+                    },
+                }
+                button { onclick: move |_| url_status.set(Some(check_json_rpc_url(&json_rpc_url.read()))),
+                    "Test"
+                }
+                StatusMessage { status: url_status() }
+            }
+            div {
+                label { r#for: "authority_keypair", "authority keypair:" }
+                input {
+                    id: "authority_keypair",
+                    name: "authority_keypair",
+                    value: "{authority_keypair}",
+                    oninput: move |event| {
+                        authority_keypair.set(event.value());
+                        authority_status.set(None);
+                    },
+                }
+                button {
+                    onclick: move |_| {
+                        authority_status.set(Some(check_authority_keypair(&authority_keypair.read())))
+                    },
+                    "Check"
+                }
+                StatusMessage { status: authority_status() }
+            }
+            button { onclick: save, "Save" }
+            StatusMessage { status: save_status() }
+        }
+    }
+}
+
+#[component]
+fn NavBar() -> Element {
+    rsx! {
+        nav {
+            Link { to: Route::Main {}, "Holdings" }
+            Link { to: Route::Disposed {}, "Disposed" }
+            Link { to: Route::Validators {}, "Validators" }
+            Link { to: Route::History {}, "History" }
+            Link { to: Route::Simulator {}, "What-if" }
+            Link { to: Route::Settings {}, "Tax rates" }
+            Epoch {}
+        }
+        ReadOnly {}
+        Outlet::<Route> {}
+    }
+}
+
+#[derive(Clone, PartialEq)]
+struct EpochStatus {
+    pub epoch: u64,
+    pub slot_index: u64,
+    pub slots_in_epoch: u64,
+    pub end_timestamp: i64,
+    pub transitions: Vec<String>,
+}
+
+const EPOCH_REFRESH_SECS: u64 = 60;
+
+#[component]
+fn Epoch() -> Element {
+    let mut status = use_signal(|| None::<Result<EpochStatus, String>>);
+    let mut now = use_signal(|| Utc::now().timestamp());
+    use_future(move || async move {
+        let mut ticks = 0;
+        loop {
+            if ticks % EPOCH_REFRESH_SECS == 0 {
+                // the rpc calls block, so they run on their own thread with
+                // neither lock held
+                let url = RPC.read().unwrap().default().url();
+                let accounts = DB.read().unwrap().get_accounts();
+                let epoch_status = tokio::task::spawn_blocking(move || {
+                    get_epoch_status(&RpcClient::new(url), &accounts).map_err(|e| e.to_string())
+                })
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()));
+                status.set(Some(epoch_status));
+            }
+            now.set(Utc::now().timestamp());
+            ticks += 1;
+            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        }
+    });
+    match status() {
+        None => rsx! { div { id: "epoch", "Epoch: loading" } },
+        Some(Err(e)) => rsx! { div { id: "epoch", "Epoch: {e}" } },
+        Some(Ok(status)) => {
+            let percent = 100. * status.slot_index as f64 / status.slots_in_epoch as f64;
+            let end = format_timestamp(status.end_timestamp);
+            let countdown = format_countdown(status.end_timestamp - now());
+            let transitions = status.transitions.join("; ");
+            rsx! {
+                div { id: "epoch",
+                    title: if transitions.is_empty() { "No stake changes at the epoch boundary" } else { "{transitions}" },
+                    "Epoch {status.epoch} "
+                    progress {
+                        max: "{status.slots_in_epoch}",
+                        value: "{status.slot_index}",
+                    }
+                    " {percent:.1}% ends {end} in {countdown}"
+                    if !transitions.is_empty() {
+                        br {}
+                        "At the boundary: {transitions}"
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn format_countdown(secs: i64) -> String {
+    if secs <= 0 {
+        return "any moment".to_string();
+    }
+    let (days, hours, minutes, secs) = (
+        secs / 86400,
+        secs % 86400 / 3600,
+        secs % 3600 / 60,
+        secs % 60,
+    );
+    if days > 0 {
+        format!("{days}d {hours:02}:{minutes:02}:{secs:02}")
+    } else {
+        format!("{hours:02}:{minutes:02}:{secs:02}")
+    }
+}
+
+#[component]
+fn ReadOnly() -> Element {
+    let read_only = use_context::<GlobalState>().read_only.read().clone();
+    if let Some(holder) = read_only {
+        rsx! {
+            div { id: "read_only",
+                "Read-only mode: the sys database is locked by {holder}. "
+                "Signing and sync are disabled until the lock is released."
+            }
+        }
+    } else {
+        rsx! {}
+    }
+}
+
+#[component]
+pub fn Main() -> Element {
+    rsx! {
+        Menu {}
+        div { id: "sys",
+            Accounts {}
+            Lots {}
+            Tokens {}
+        }
+        Input {}
+        LotPreview {}
+        Summary {}
+        Log {}
+        ConfirmDialog {}
+    }
+}
+
+#[component]
+pub fn Menu() -> Element {
+    let account = use_context::<GlobalState>().account.read().clone();
+    let address = account.map(|x| x.address);
+    let mut state = use_context::<GlobalState>().state;
+    let sync = move |_| {
+        if state.read().dry_run {
+            consume_context::<GlobalState>()
+                .log
+                .set(Some("Dry run is on, sync was not run".to_string()));
+            return;
+        }
+        spawn(async move {
+            let mut buffer = std::io::BufWriter::new(Vec::new());
+            let xclients = use_context::<GlobalState>().xclients;
+            let xclients = xclients.read();
+            let exchange_client = xclients.as_ref().unwrap().get(&Exchange::Coinbase).unwrap();
+            match process_account_sync(
+                &mut DB.write().unwrap(),
+                &RPC.read().unwrap(),
+                exchange_client.as_ref(),
+                address,
+                None,
+                false,
+                false,
+                &Notifier::default(),
+                &mut buffer,
+            )
+            .await
             {
                 Ok(()) => {
                     let bytes = buffer.into_inner().unwrap();
@@ -288,38 +1172,11 @@ pub fn Menu() -> Element {
             consume_context::<GlobalState>().xupdate.set(true);
         });
     };
-    let mut account = use_context::<GlobalState>().account;
-    let mut state = use_context::<GlobalState>().state;
-    let split = move |_| {
-        spawn(async move {
-            do_split(&mut account, &mut state).await;
-            *(use_context::<GlobalState>().reload.write()) = true;
-        });
-    };
-    let deactivate = move |_| {
-        spawn(async move { do_deactivate(&mut account, &state).await });
-    };
-    let withdraw = move |_| {
-        spawn(async move {
-            do_withdraw(&mut account, &mut state).await;
-            *(use_context::<GlobalState>().reload.write()) = true;
-            *(use_context::<GlobalState>().xupdate.write()) = true;
-        });
-    };
-    let delegate = move |_| {
-        spawn(async move { do_delegate(&mut account, &state).await });
-    };
-    let swap = move |_| {
-        spawn(async move {
-            do_swap(&mut account, &mut state).await;
-            *(use_context::<GlobalState>().reload.write()) = true;
-        });
-    };
-    let merge = move |_| {
-        spawn(async move {
-            do_merge(&mut account, &state).await;
-            *(use_context::<GlobalState>().reload.write()) = true;
-        });
+    let account = use_context::<GlobalState>().account;
+    let preview = move |operation| {
+        move |_: Event<MouseData>| {
+            spawn(async move { do_preview(operation, &account, &state).await });
+        }
     };
     let xclients = use_context::<GlobalState>().xclients;
     let disburse = move |_| {
@@ -331,16 +1188,67 @@ pub fn Menu() -> Element {
         });
     };
     let url = state.read().url.clone().unwrap_or_default();
+    let dry_run = state.read().dry_run;
+    let harvest = state.read().harvest;
+    let profile = state.read().profile.clone();
+    let read_only = use_context::<GlobalState>().read_only.read().is_some();
     rsx! {
         div { id: "menu",
-            button { onclick: sync, "Sync" }
-            button { onclick: split, "Split" }
-            button { onclick: deactivate, "Deactivate" }
-            button { onclick: withdraw, "Withdraw" }
-            button { onclick: delegate, "Delegate" }
-            button { onclick: swap, "Swap" }
-            button { onclick: merge, "Merge" }
-            button { onclick: disburse, "Disburse" }
+            button { onclick: sync, disabled: read_only, "Sync" }
+            button { onclick: preview(Operation::Split), disabled: read_only, "Split" }
+            button { onclick: preview(Operation::Deactivate), disabled: read_only, "Deactivate" }
+            button { onclick: preview(Operation::Withdraw), disabled: read_only, "Withdraw" }
+            button { onclick: preview(Operation::Delegate), disabled: read_only, "Delegate" }
+            button { onclick: preview(Operation::Swap), disabled: read_only, "Swap" }
+            button { onclick: preview(Operation::Merge), disabled: read_only, "Merge" }
+            button { onclick: preview(Operation::NewStake), disabled: read_only, "New stake" }
+            button { onclick: preview(Operation::Authorize), disabled: read_only, "Authorize" }
+            button { onclick: preview(Operation::SetLockup), disabled: read_only, "Set lockup" }
+            button { onclick: preview(Operation::MoveStake), disabled: read_only, "Move stake" }
+            button { onclick: preview(Operation::MoveLamports), disabled: read_only, "Move lamports" }
+            button { onclick: disburse, disabled: read_only, "Disburse" }
+            label { r#for: "dry_run", "dry run:" }
+            input {
+                id: "dry_run",
+                name: "dry_run",
+                r#type: "checkbox",
+                checked: dry_run,
+                oninput: move |event| state.write().dry_run = event.checked(),
+            }
+            label { r#for: "harvest", "harvest losses:" }
+            input {
+                id: "harvest",
+                name: "harvest",
+                r#type: "checkbox",
+                checked: harvest,
+                oninput: move |event| {
+                    let harvest = event.checked();
+                    state.write().harvest = harvest;
+                    let mut selected = use_context::<GlobalState>().selected;
+                    *selected.write() = if harvest {
+                        let prices = use_context::<GlobalState>().prices.read().clone();
+                        harvest_lots(&DB.read().unwrap(), &prices)
+                            .iter()
+                            .map(|x| x.lot.lot_number)
+                            .collect()
+                    } else {
+                        BTreeSet::default()
+                    };
+                },
+            }
+            label { r#for: "profile", "profile:" }
+            select {
+                id: "profile",
+                name: "profile",
+                onchange: move |event| switch_profile(event.value()),
+                for name in CONFIG.profile_names() {
+                    option {
+                        value: "{name}",
+                        selected: name == profile,
+                        "{name}"
+                    }
+                }
+            }
             label { r#for: "json_rpc_url", "url:" }
             input {
                 id: "json_rpc_url",
@@ -358,6 +1266,57 @@ pub fn Menu() -> Element {
     }
 }
 
+fn switch_profile(name: String) {
+    let mut log = use_context::<GlobalState>().log;
+    let profile = match CONFIG.profile(&name) {
+        Some(profile) => profile,
+        None => {
+            *log.write() = Some(format!("Unknown profile {name}"));
+            return;
+        }
+    };
+    // the profile may share the database, so this process's lock is
+    // released before locking it again
+    let previous = use_context::<GlobalState>().state.read().profile.clone();
+    let was_locked = DB_LOCK.lock().unwrap().take().is_some();
+    let (db, holder) = match open_db(&profile.db_path) {
+        Ok(v) => v,
+        Err(e) => {
+            if let Some(previous) = CONFIG.profile(&previous).filter(|_| was_locked) {
+                match lock_db(std::path::Path::new(&previous.db_path)) {
+                    Ok(lock) => *DB_LOCK.lock().unwrap() = Some(lock),
+                    Err(holder) => use_context::<GlobalState>().read_only.set(Some(holder)),
+                }
+            }
+            *log.write() = Some(format!("Failed to switch to profile {name}: {e}"));
+            return;
+        }
+    };
+    *DB.write().unwrap() = db;
+    let xclients = exchange_clients(&DB.read().unwrap());
+    use_context::<GlobalState>()
+        .xclients
+        .set(Some(Arc::new(xclients)));
+    use_context::<GlobalState>().read_only.set(holder);
+    *RPC.write().unwrap() = RpcClients::new(profile.json_rpc_url.clone(), None, None);
+    let mut state = use_context::<GlobalState>().state;
+    {
+        let mut state = state.write();
+        state.profile = name.clone();
+        state.url = Some(profile.json_rpc_url);
+        state.authority = profile.authority_keypair;
+        state.recipient = None;
+    }
+    use_context::<GlobalState>().account.set(None);
+    use_context::<GlobalState>().selected.write().clear();
+    use_context::<GlobalState>()
+        .disposed_selected
+        .write()
+        .clear();
+    use_context::<GlobalState>().reload.set(true);
+    *log.write() = Some(format!("Switched to profile {name} ({})", profile.db_path));
+}
+
 #[component]
 pub fn Accounts() -> Element {
     rsx! {
@@ -391,15 +1350,34 @@ pub fn AccountsList() -> Element {
 pub fn AccountState() -> Element {
     let account = use_context::<GlobalState>().account.read().clone();
     let rpc = RPC.read().unwrap();
+    let mut rewards = use_signal(|| false);
 
     if let Some(account) = account {
         let content = account.description.to_string();
+        if account.token.is_sol() && rewards() {
+            return rsx! {
+                div { id: "account_state",
+                    div { class: "tabs",
+                        button { onclick: move |_| rewards.set(false), "State" }
+                        button { class: "selected", "Rewards" }
+                    }
+                    pre { "{content}" }
+                    Rewards { account: account.clone() }
+                }
+            };
+        }
         let mut buffer = std::io::BufWriter::new(Vec::new());
         if get_account_state(&rpc, account.token, account.address, &mut buffer).is_ok() {
             let bytes = buffer.into_inner().unwrap();
             let account_state = String::from_utf8(bytes).unwrap();
             rsx! {
                 div { id: "account_state",
+                    if account.token.is_sol() {
+                        div { class: "tabs",
+                            button { class: "selected", "State" }
+                            button { onclick: move |_| rewards.set(true), "Rewards" }
+                        }
+                    }
                     pre {
                         "{content}"
                         br {}
@@ -419,6 +1397,69 @@ pub fn AccountState() -> Element {
     }
 }
 
+#[derive(Clone, PartialEq)]
+struct RewardInfo {
+    pub epoch: u64,
+    pub amount: u64,
+    pub post_balance: u64,
+    pub commission: Option<u8>,
+    /// None when the reward is the whole balance and there is no rate.
+    pub apy: Option<f64>,
+    pub lot: Option<String>,
+}
+
+#[component]
+fn Rewards(account: TrackedAccount) -> Element {
+    let address = account.address;
+    let rewards = use_resource(use_reactive!(|(address,)| async move {
+        // the rpc calls block, so they run on their own thread with
+        // neither lock held
+        let url = RPC.read().unwrap().default().url();
+        let (accounts, disposed) = {
+            let db = DB.read().unwrap();
+            (db.get_accounts(), db.disposed_lots())
+        };
+        tokio::task::spawn_blocking(move || {
+            get_rewards(&RpcClient::new(url), &accounts, &disposed, address)
+                .map_err(|e| e.to_string())
+        })
+        .await
+        .unwrap_or_else(|e| Err(e.to_string()))
+    }));
+    let rewards = rewards.read();
+    let rewards = match rewards.as_ref() {
+        None => return rsx! { "Loading rewards" },
+        Some(Err(e)) => return rsx! { "Failed to get rewards: {e}" },
+        Some(Ok(rewards)) => rewards,
+    };
+    rsx! {
+        table {
+            tr {
+                th { "Epoch" }
+                th { "Reward" }
+                th { "Balance" }
+                th { "Commission" }
+                th { "APY" }
+                th { "Lot" }
+            }
+            for reward in rewards {
+                tr { class: if reward.lot.is_none() { "missing" } else { "regular" },
+                    td { "{reward.epoch}" }
+                    td { {MaybeToken::SOL().format_amount(reward.amount)} }
+                    td { {MaybeToken::SOL().format_amount(reward.post_balance)} }
+                    td {
+                        {reward.commission.map(|x| format!("{x}%")).unwrap_or_default()}
+                    }
+                    td {
+                        {reward.apy.map(|x| format!("{x:.2}%")).unwrap_or_else(|| "-".to_string())}
+                    }
+                    td { {reward.lot.clone().unwrap_or_else(|| "missing".to_string())} }
+                }
+            }
+        }
+    }
+}
+
 #[component]
 pub fn Exchanges() -> Element {
     let exchanges = DB.read().unwrap().get_exchanges();
@@ -585,128 +1626,194 @@ fn PaymentMethodsItem(exchange: Exchange, method: PaymentInfo) -> Element {
 
 #[component]
 pub fn Lots() -> Element {
-    let mut state = use_context::<GlobalState>().state;
+    let state = use_context::<GlobalState>().state;
     let account = use_context::<GlobalState>().account.read().clone();
     let prices = use_context::<GlobalState>().prices.read().clone();
+    let lots_table = use_context::<GlobalState>().lots_table;
+    let selected = use_context::<GlobalState>().selected;
+
+    if state.read().harvest {
+        return rsx! { HarvestLots {} };
+    }
 
     if let Some(account) = account {
-        let price = *prices.get(&account.token.to_string()).unwrap_or(&0f64);
-        let mut lots = account.lots;
-        if let Some(ref sorting) = state.read().sorted {
-            match *sorting {
-                Sorting::Lot(d) => {
-                    lots.sort_by(|a, b| {
-                        if d {
-                            a.lot_number.cmp(&b.lot_number)
-                        } else {
-                            b.lot_number.cmp(&a.lot_number)
-                        }
-                    });
-                }
-                Sorting::Date(d) => {
-                    lots.sort_by(|a, b| {
-                        if d {
-                            a.acquisition.when.cmp(&b.acquisition.when)
-                        } else {
-                            b.acquisition.when.cmp(&a.acquisition.when)
-                        }
-                    });
-                }
-                Sorting::Amount(d) => {
-                    lots.sort_by(|a, b| {
-                        if d {
-                            a.amount.cmp(&b.amount)
-                        } else {
-                            b.amount.cmp(&a.amount)
-                        }
-                    });
-                }
-                Sorting::Price(d) => {
-                    lots.sort_by(|a, b| {
-                        if d {
-                            a.acquisition.price().cmp(&b.acquisition.price())
-                        } else {
-                            b.acquisition.price().cmp(&a.acquisition.price())
-                        }
-                    });
-                }
+        let price = prices.get(&account.token.to_string()).map(|x| x.value);
+        let rows = account
+            .lots
+            .into_iter()
+            .map(|lot| LotRow {
+                token: account.token,
+                lot,
+                price,
+            })
+            .collect::<Vec<_>>();
+        rsx! {
+            Table {
+                id: "lots",
+                columns: lot_columns(),
+                rows,
+                state: lots_table,
+                selected,
             }
         }
+    } else {
         rsx! {
-            div { id: "lots",
-                table {
+            div { id: "lots" }
+        }
+    }
+}
+
+#[derive(Clone, PartialEq)]
+struct HarvestLot {
+    pub description: String,
+    pub token: MaybeToken,
+    pub lot: Lot,
+    pub loss: Decimal,
+    pub long_term: bool,
+    pub wash_sale_risk: Vec<usize>,
+}
+
+/// Lots of all accounts worth less than their basis at the current prices,
+/// short-term first, largest loss first.
+fn harvest_lots(db: &Db, prices: &BTreeMap<String, Price>) -> Vec<HarvestLot> {
+    let today = Local::now().date_naive();
+    let accounts = db.get_accounts();
+    let disposed = db.disposed_lots();
+    let all_lots = accounts
+        .iter()
+        .flat_map(|x| x.lots.iter().map(|lot| (x.token, lot)))
+        .chain(disposed.iter().map(|x| (x.token, &x.lot)))
+        .collect::<Vec<_>>();
+    let mut lots = vec![];
+    for account in &accounts {
+        let Some(price) = prices.get(&account.token.to_string()) else {
+            continue;
+        };
+        for lot in &account.lots {
+            let basis = lot.acquisition.price();
+            if price.value >= basis {
+                continue;
+            }
+            lots.push(HarvestLot {
+                description: account.description.clone(),
+                token: account.token,
+                lot: lot.clone(),
+                loss: ui_amount(account.token, lot.amount) * (basis - price.value),
+                long_term: holding::is_long_term(lot.acquisition.when, today),
+                wash_sale_risk: tax::wash_sale_risk(
+                    account.token,
+                    lot.lot_number,
+                    today,
+                    all_lots.iter().copied(),
+                ),
+            });
+        }
+    }
+    lots.sort_by(|a, b| a.long_term.cmp(&b.long_term).then(b.loss.cmp(&a.loss)));
+    lots
+}
+
+#[component]
+fn HarvestLots() -> Element {
+    let prices = use_context::<GlobalState>().prices.read().clone();
+    let selected = use_context::<GlobalState>().selected;
+    let db = DB.read().unwrap();
+    let lots = harvest_lots(&db, &prices);
+    let rates = tax_rates(&db, &use_context::<GlobalState>().extra_tax_rates.read());
+    let (short_loss, long_loss) = lots
+        .iter()
+        .filter(|x| selected.read().contains(&x.lot.lot_number))
+        .fold((Decimal::ZERO, Decimal::ZERO), |acc, x| {
+            if x.long_term {
+                (acc.0, acc.1 + x.loss)
+            } else {
+                (acc.0 + x.loss, acc.1)
+            }
+        });
+    let saving = short_loss * rates.short_term_gain + long_loss * rates.long_term_gain;
+    let summary = format!(
+        "harvested short-term loss ${} long-term loss ${}, estimated tax saving ${}",
+        usd(short_loss),
+        usd(long_loss),
+        usd(saving),
+    );
+    rsx! {
+        div { id: "lots",
+            table {
+                tr {
+                    th { "Account" }
+                    th { id: "lot_number", "Lot" }
+                    th { "Date" }
+                    th { id: "lot_amount", "Amount" }
+                    th { id: "lot_price", "Price" }
+                    th { "Loss" }
+                }
+                for (term , long_term) in [("Short-term", false), ("Long-term", true)] {
                     tr {
-                        th {
-                            id: "lot_number",
-                            onclick: move |_| {
-                                let sorted = state.read().sorted.clone();
-                                let mut v = true;
-                                if let Some(Sorting::Lot(x)) = sorted {
-                                    v = !x;
-                                }
-                                state.write().sorted = Some(Sorting::Lot(v));
-                            },
-                            "Lot"
-                        }
-                        th {
-                            onclick: move |_| {
-                                let sorted = state.read().sorted.clone();
-                                let mut v = true;
-                                if let Some(Sorting::Date(x)) = sorted {
-                                    v = !x;
-                                }
-                                state.write().sorted = Some(Sorting::Date(v));
-                            },
-                            "Date"
-                        }
-                        th {
-                            id: "lot_amount",
-                            onclick: move |_| {
-                                let sorted = state.read().sorted.clone();
-                                let mut v = true;
-                                if let Some(Sorting::Amount(x)) = sorted {
-                                    v = !x;
-                                }
-                                state.write().sorted = Some(Sorting::Amount(v));
-                            },
-                            "Amount"
-                        }
-                        th {
-                            id: "lot_price",
-                            onclick: move |_| {
-                                let sorted = state.read().sorted.clone();
-                                let mut v = true;
-                                if let Some(Sorting::Price(x)) = sorted {
-                                    v = !x;
-                                }
-                                state.write().sorted = Some(Sorting::Price(v));
-                            },
-                            "Price"
-                        }
-                        th { id: "lot_term", "Term" }
-                        th { "Gain" }
+                        th { colspan: 6, "{term}" }
                     }
-                    for lot in lots {
-                        LotItem {
-                            token: account.token,
-                            lot: lot.clone(),
-                            price,
-                        }
+                    for lot in lots.iter().filter(|x| x.long_term == long_term).cloned() {
+                        HarvestLotItem { lot }
                     }
                 }
             }
-        }
-    } else {
-        rsx! {
-            div { id: "lots" }
+            pre { "{summary}" }
         }
     }
 }
 
 #[component]
-fn AccountItem(account: TrackedAccount) -> Element {
-    let mut state = use_context::<GlobalState>().state;
-    let selected_account = use_context::<GlobalState>().account.read().clone();
+fn HarvestLotItem(lot: HarvestLot) -> Element {
+    let mut selected = use_context::<GlobalState>().selected;
+    let lot_number = lot.lot.lot_number;
+    let amount = lot.token.format_amount(lot.lot.amount);
+    let price = format!("${}", usd(lot.lot.acquisition.price()));
+    let loss = format!("${}", usd(lot.loss));
+    let warning = if lot.wash_sale_risk.is_empty() {
+        let until = Local::now().date_naive() + chrono::Duration::days(tax::WASH_SALE_DAYS);
+        format!("Buying {} again before {until} washes this loss", lot.token)
+    } else {
+        format!(
+            "Wash sale: lots {} were bought within {} days",
+            lot.wash_sale_risk
+                .iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+            tax::WASH_SALE_DAYS,
+        )
+    };
+    let kind = if selected.read().contains(&lot_number) {
+        "selected"
+    } else if !lot.wash_sale_risk.is_empty() {
+        "wash"
+    } else {
+        "regular"
+    };
+    rsx! {
+        tr {
+            class: kind,
+            title: warning,
+            onclick: move |_| {
+                let mut selected = selected.write();
+                if !selected.remove(&lot_number) {
+                    selected.insert(lot_number);
+                }
+            },
+            td { "{lot.description}" }
+            td { class: "lot_number", "{lot_number}" }
+            td { class: "lot_date", "{lot.lot.acquisition.when}" }
+            td { class: "lot_amount", "{amount}" }
+            td { class: "lot_price", "{price}" }
+            td { "{loss}" }
+        }
+    }
+}
+
+#[component]
+fn AccountItem(account: TrackedAccount) -> Element {
+    let mut state = use_context::<GlobalState>().state;
+    let selected_account = use_context::<GlobalState>().account.read().clone();
     let address = format!(
         "{:?} ({}) {}",
         account.address,
@@ -745,139 +1852,140 @@ fn AccountItem(account: TrackedAccount) -> Element {
     }
 }
 
-#[component]
-fn LotItem(token: MaybeToken, lot: Lot, price: f64) -> Element {
-    let mut selected = use_context::<GlobalState>().selected;
-    let lot_number = format!("{}", lot.lot_number);
-    let lot_amount = token.format_amount(lot.amount).to_string();
-    let lot_date = format!("{}", lot.acquisition.when);
-    let lot_price = format!(
-        "${}",
-        f64::try_from(lot.acquisition.price())
-            .unwrap()
-            .separated_string_with_fixed_place(2)
-    );
-    let today = chrono::Local::now().date_naive();
-    let term = if today.signed_duration_since(lot.acquisition.when).num_days() < 365 {
-        "S"
-    } else {
-        "L"
-    };
-    let gain = format!(
-        "${}",
-        (token.ui_amount(lot.amount) * (price - f64::try_from(lot.acquisition.price()).unwrap()))
-            .separated_string_with_fixed_place(2)
-    );
-    let kind = if selected.read().contains(&lot.lot_number) {
-        "selected"
-    } else {
-        "regular"
-    };
-    let account = use_context::<GlobalState>().account.read().clone();
-    let sorted = use_context::<GlobalState>().state.read().sorted.clone();
-    let select_lot = move |event: Event<MouseData>| {
-        let lot = lot.lot_number;
-        let mut selected = selected.write();
-        let modifiers = event.data().modifiers();
-        if modifiers.shift() {
-            if let Some(ref account) = account {
-                let mut lots = account.lots.clone();
-                if let Some(sorting) = sorted.clone() {
-                    match sorting {
-                        Sorting::Lot(d) => {
-                            lots.sort_by(|a, b| {
-                                if d {
-                                    a.lot_number.cmp(&b.lot_number)
-                                } else {
-                                    b.lot_number.cmp(&a.lot_number)
-                                }
-                            });
-                        }
-                        Sorting::Date(d) => {
-                            lots.sort_by(|a, b| {
-                                if d {
-                                    a.acquisition.when.cmp(&b.acquisition.when)
-                                } else {
-                                    b.acquisition.when.cmp(&a.acquisition.when)
-                                }
-                            });
-                        }
-                        Sorting::Amount(d) => {
-                            lots.sort_by(|a, b| {
-                                if d {
-                                    a.amount.cmp(&b.amount)
-                                } else {
-                                    b.amount.cmp(&a.amount)
-                                }
-                            });
-                        }
-                        Sorting::Price(d) => {
-                            lots.sort_by(|a, b| {
-                                if d {
-                                    a.acquisition.price().cmp(&b.acquisition.price())
-                                } else {
-                                    b.acquisition.price().cmp(&a.acquisition.price())
-                                }
-                            });
-                        }
-                    }
-                }
-                let mut sel_end = 0;
-                let mut sel_beg = 0;
-                for (i, l) in lots.iter().enumerate() {
-                    if l.lot_number == lot {
-                        sel_end = i;
-                    } else if selected.contains(&l.lot_number) {
-                        sel_beg = i;
-                    }
-                }
-                if sel_beg > sel_end {
-                    std::mem::swap(&mut sel_beg, &mut sel_end);
-                }
-                for i in sel_beg..=sel_end {
-                    selected.insert(lots[i].lot_number);
-                }
-            }
-        } else if modifiers.meta() {
-            if selected.contains(&lot) {
-                selected.remove(&lot);
-            } else {
-                selected.insert(lot);
-            }
-        } else {
-            selected.clear();
-            selected.insert(lot);
-        }
-    };
-    rsx! {
-        tr {
-            class: kind,
-            onclick: select_lot,
-            td { class: "lot_number", "{lot_number}" }
-            td { class: "lot_date", "{lot_date}" }
-            td { class: "lot_amount", "{lot_amount}" }
-            td { class: "lot_price", "{lot_price}" }
-            td { class: "lot_term", "{term}" }
-            td { "{gain}" }
-        }
+#[derive(Clone, PartialEq)]
+struct LotRow {
+    pub token: MaybeToken,
+    pub lot: Lot,
+    pub price: Option<Decimal>,
+}
+
+impl TableRow for LotRow {
+    fn id(&self) -> usize {
+        self.lot.lot_number
+    }
+}
+
+impl LotRow {
+    /// Unrealized gain, none while the token has no price.
+    fn gain(&self) -> Option<Decimal> {
+        self.price.map(|price| {
+            ui_amount(self.token, self.lot.amount) * (price - self.lot.acquisition.price())
+        })
+    }
+
+    fn holding_period(&self) -> HoldingPeriod {
+        HoldingPeriod::new(self.lot.acquisition.when)
     }
 }
 
+fn lot_columns() -> Vec<Column<LotRow>> {
+    vec![
+        Column::new(
+            "lot",
+            "Lot",
+            |x| SortKey::Number(x.lot.lot_number.into()),
+            |x| x.lot.lot_number.to_string(),
+        )
+        .with_class("lot_number"),
+        Column::new(
+            "date",
+            "Date",
+            |x| SortKey::Date(x.lot.acquisition.when),
+            |x| x.lot.acquisition.when.to_string(),
+        )
+        .with_class("lot_date"),
+        Column::new(
+            "amount",
+            "Amount",
+            |x| SortKey::Number(ui_amount(x.token, x.lot.amount)),
+            |x| x.token.format_amount(x.lot.amount).to_string(),
+        )
+        .with_class("lot_amount"),
+        Column::new(
+            "price",
+            "Price",
+            |x| SortKey::Number(x.lot.acquisition.price()),
+            |x| format!("${}", usd(x.lot.acquisition.price())),
+        )
+        .with_class("lot_price"),
+        // by the date the lot turns long-term
+        Column::new(
+            "term",
+            "Term",
+            |x| SortKey::Date(x.holding_period().long_term_date()),
+            |x| {
+                x.holding_period()
+                    .term(chrono::Local::now().date_naive())
+                    .to_string()
+            },
+        )
+        .with_class("lot_term")
+        .with_tooltip(|x| {
+            x.holding_period()
+                .describe(chrono::Local::now().date_naive())
+        }),
+        Column::new(
+            "gain",
+            "Gain",
+            |x| SortKey::Number(x.gain().unwrap_or_default()),
+            |x| x.gain().map(|x| format!("${}", usd(x))).unwrap_or_default(),
+        ),
+    ]
+}
+
+fn price_tokens() -> Vec<MaybeToken> {
+    let mut tokens = vec![MaybeToken::from(None)];
+    tokens.append(
+        &mut Token::VARIANTS
+            .into_iter()
+            .map(|x| MaybeToken::from(Some(x)))
+            .collect::<Vec<MaybeToken>>(),
+    );
+    tokens
+}
+
 #[component]
 fn Tokens() -> Element {
     let prices = use_context::<GlobalState>().prices.read().clone();
+    let mut chart = use_signal(|| None::<String>);
     rsx! {
         div { id: "tokens",
+            if let Some(token) = chart() {
+                PriceChart { token }
+            }
             table {
-                for (token , price) in prices.into_iter() {
+                for token in price_tokens().into_iter().map(|x| x.to_string()) {
                     tr {
                         class: "token",
-                        onclick: move |_| {
-                            let mut state = use_context::<GlobalState>().state;
-                            state.write().recipient = Some(token.to_string());
+                        onclick: {
+                            let token = token.clone();
+                            move |_| {
+                                let mut state = use_context::<GlobalState>().state;
+                                state.write().recipient = Some(token.clone());
+                            }
                         },
                         td { class: "token", "{token}" }
-                        td { class: "token", "${price}" }
+                        if let Some(price) = prices.get(&token) {
+                            td {
+                                class: if price.is_stale() { "token stale" } else { "token" },
+                                title: price.describe(),
+                                "${price.value}"
+                            }
+                        } else {
+                            td { class: "token missing", "no price" }
+                        }
+                        td { class: "token",
+                            button {
+                                onclick: {
+                                    let token = token.clone();
+                                    move |event: Event<MouseData>| {
+                                        event.stop_propagation();
+                                        chart.set(if chart() == Some(token.clone()) { None } else { Some(token.clone()) });
+                                    }
+                                },
+                                "chart"
+                            }
+                        }
                     }
                 }
             }
@@ -885,12 +1993,207 @@ fn Tokens() -> Element {
     }
 }
 
+#[derive(Clone, Copy, PartialEq)]
+enum ChartRange {
+    Day,
+    Week,
+    Month,
+    All,
+}
+
+impl ChartRange {
+    const ALL: [ChartRange; 4] = [
+        ChartRange::Day,
+        ChartRange::Week,
+        ChartRange::Month,
+        ChartRange::All,
+    ];
+
+    fn since(&self) -> Option<DateTime<Utc>> {
+        let days = match self {
+            ChartRange::Day => 1,
+            ChartRange::Week => 7,
+            ChartRange::Month => 30,
+            ChartRange::All => return None,
+        };
+        Some(Utc::now() - chrono::Duration::days(days))
+    }
+}
+
+impl std::fmt::Display for ChartRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ChartRange::Day => write!(f, "1D"),
+            ChartRange::Week => write!(f, "1W"),
+            ChartRange::Month => write!(f, "1M"),
+            ChartRange::All => write!(f, "All"),
+        }
+    }
+}
+
+#[component]
+fn ChartRanges(range: Signal<ChartRange>) -> Element {
+    rsx! {
+        div { class: "tabs",
+            for r in ChartRange::ALL {
+                button {
+                    class: if r == range() { "selected" } else { "regular" },
+                    onclick: move |_| range.set(r),
+                    "{r}"
+                }
+            }
+        }
+    }
+}
+
+#[component]
+fn PriceChart(token: String) -> Element {
+    let range = use_signal(|| ChartRange::Day);
+    let mut date = use_signal(|| None::<NaiveDate>);
+    let series = PRICE_HISTORY
+        .samples(&token, range().since())
+        .into_iter()
+        .map(|(when, value)| (when.timestamp(), value))
+        .collect::<Vec<_>>();
+    let price_on_date = date().map(|date| {
+        let end_of_day = date
+            .and_hms_opt(23, 59, 59)
+            .unwrap()
+            .and_local_timezone(Local)
+            .earliest()
+            .map(|x| x.with_timezone(&Utc))
+            .unwrap_or_else(Utc::now);
+        match PRICE_HISTORY.price_at(&token, end_of_day) {
+            Some(price) => format!("{token} on {date}: ${price}"),
+            None => format!("no {token} price recorded by {date}"),
+        }
+    });
+    rsx! {
+        div { class: "chart",
+            ChartRanges { range }
+            LineChart { series: vec![(token.clone(), series)] }
+            label { r#for: "price_date", "price on:" }
+            input {
+                id: "price_date",
+                r#type: "date",
+                oninput: move |event| date.set(NaiveDate::parse_from_str(&event.value(), "%Y-%m-%d").ok()),
+            }
+            if let Some(price_on_date) = price_on_date {
+                " {price_on_date}"
+            }
+        }
+    }
+}
+
+const CHART_WIDTH: f64 = 600.;
+const CHART_HEIGHT: f64 = 150.;
+const CHART_POINTS: usize = 600;
+
+/// Draws each series of (timestamp, value) samples as a polyline on a
+/// shared scale.
+#[component]
+fn LineChart(series: Vec<(String, Vec<(i64, f64)>)>) -> Element {
+    let samples = series.iter().flat_map(|x| x.1.iter());
+    let (mut t0, mut t1, mut v0, mut v1) = (i64::MAX, i64::MIN, f64::MAX, f64::MIN);
+    for (t, v) in samples {
+        (t0, t1, v0, v1) = (t0.min(*t), t1.max(*t), v0.min(*v), v1.max(*v));
+    }
+    if t0 > t1 {
+        return rsx! { div { class: "chart", "No samples recorded yet" } };
+    }
+    let dt = (t1 - t0).max(1) as f64;
+    let dv = if v1 > v0 { v1 - v0 } else { 1. };
+    let lines = series
+        .iter()
+        .map(|(name, samples)| {
+            let step = samples.len().div_ceil(CHART_POINTS).max(1);
+            let points = samples
+                .iter()
+                .step_by(step)
+                .chain(samples.last())
+                .map(|(t, v)| {
+                    format!(
+                        "{:.1},{:.1}",
+                        (t - t0) as f64 / dt * CHART_WIDTH,
+                        CHART_HEIGHT - (v - v0) / dv * CHART_HEIGHT
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(" ");
+            (name.clone(), points)
+        })
+        .collect::<Vec<_>>();
+    let range = format!(
+        "${} – ${}, {} – {}",
+        v0.separated_string_with_fixed_place(2),
+        v1.separated_string_with_fixed_place(2),
+        format_timestamp(t0),
+        format_timestamp(t1)
+    );
+    rsx! {
+        svg {
+            view_box: "0 0 {CHART_WIDTH} {CHART_HEIGHT}",
+            preserve_aspect_ratio: "none",
+            for (i , (_ , points)) in lines.iter().enumerate() {
+                polyline { class: "series{i}", fill: "none", points: "{points}" }
+            }
+        }
+        div { class: "legend",
+            for (i , (name , _)) in lines.iter().enumerate() {
+                span { class: "series{i}", "{name} " }
+            }
+            "{range}"
+        }
+    }
+}
+
+/// Shows which lots an operation on the selected account would consume.
+#[component]
+fn LotPreview() -> Element {
+    let state = use_context::<GlobalState>().state;
+    let account = use_context::<GlobalState>().account.read().clone();
+    let selected = use_context::<GlobalState>().selected.read().clone();
+    let Some(account) = account else {
+        return rsx! {};
+    };
+    let selected = account_selection(&account, &selected);
+    let method = state.read().lot_method;
+    let amount = match state.read().amount {
+        Some(amount) if amount > 0. => account.token.amount(amount),
+        _ => account
+            .lots
+            .iter()
+            .filter(|x| selected.contains(&x.lot_number))
+            .fold(0, |acc, x| acc + x.amount),
+    };
+    if amount == 0 {
+        return rsx! {};
+    }
+    let preview = match select_lots(&account.lots, &selected, amount, method) {
+        Ok(used) => format!(
+            "{} by {method} uses lots {}",
+            account.token.format_amount(amount),
+            describe_lots(account.token, &used).join(", ")
+        ),
+        Err(e) => e,
+    };
+    rsx! {
+        div { id: "lot_preview", "{preview}" }
+    }
+}
+
 #[component]
 pub fn Input() -> Element {
     let mut state = use_context::<GlobalState>().state;
     let authority = state.read().authority.clone().unwrap_or_default();
     let recipient = state.read().recipient.clone().unwrap_or_default();
     let amount = state.read().amount.clone().unwrap_or_default();
+    let seed = state.read().seed.clone().unwrap_or_default();
+    let custodian = state.read().custodian.clone().unwrap_or_default();
+    let authorization = state.read().authorization;
+    let lot_method = state.read().lot_method;
+    let lockup_date = state.read().lockup_date.clone().unwrap_or_default();
+    let lockup_epoch = state.read().lockup_epoch.clone().unwrap_or_default();
     rsx! {
         div { id: "authority",
             label { r#for: "authority", "authority:" }
@@ -922,22 +2225,198 @@ pub fn Input() -> Element {
                     }
                 },
             }
+            label { r#for: "lot_method", "lots:" }
+            select {
+                id: "lot_method",
+                name: "lot_method",
+                onchange: move |event| {
+                    if let Some(method) = LotMethod::ALL
+                        .into_iter()
+                        .find(|x| x.to_string() == event.value())
+                    {
+                        state.write().lot_method = method;
+                    }
+                },
+                for method in LotMethod::ALL {
+                    option {
+                        value: "{method}",
+                        selected: method == lot_method,
+                        "{method}"
+                    }
+                }
+            }
+            label { r#for: "seed", "seed:" }
+            input {
+                id: "seed",
+                name: "seed",
+                value: seed,
+                oninput: move |event| {
+                    let value = event.value();
+                    state.write().seed = if value.is_empty() { None } else { Some(value) };
+                },
+            }
+            label { r#for: "custodian", "custodian:" }
+            input {
+                id: "custodian",
+                name: "custodian",
+                value: custodian,
+                oninput: move |event| {
+                    let value = event.value();
+                    state.write().custodian = if value.is_empty() { None } else { Some(value) };
+                },
+            }
+            label { r#for: "authorization", "authorize:" }
+            select {
+                id: "authorization",
+                name: "authorization",
+                onchange: move |event| {
+                    state.write().authorization = match event.value().as_str() {
+                        "withdrawer" => Authorization::Withdrawer,
+                        "both" => Authorization::Both,
+                        _ => Authorization::Staker,
+                    };
+                },
+                option {
+                    value: "staker",
+                    selected: authorization == Authorization::Staker,
+                    "staker"
+                }
+                option {
+                    value: "withdrawer",
+                    selected: authorization == Authorization::Withdrawer,
+                    "withdrawer"
+                }
+                option { value: "both", selected: authorization == Authorization::Both, "both" }
+            }
+            label { r#for: "lockup_date", "lockup until:" }
+            input {
+                id: "lockup_date",
+                name: "lockup_date",
+                placeholder: "YYYY-MM-DD",
+                value: lockup_date,
+                oninput: move |event| {
+                    let value = event.value();
+                    state.write().lockup_date = if value.is_empty() { None } else { Some(value) };
+                },
+            }
+            label { r#for: "lockup_epoch", "lockup epoch:" }
+            input {
+                id: "lockup_epoch",
+                name: "lockup_epoch",
+                value: lockup_epoch,
+                oninput: move |event| {
+                    let value = event.value();
+                    state.write().lockup_epoch = if value.is_empty() { None } else { Some(value) };
+                },
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct TaxRates {
+    pub income: Decimal,
+    pub short_term_gain: Decimal,
+    pub long_term_gain: Decimal,
+}
+
+/// The rates of the sys db, or the defaults when none are stored, plus the
+/// state rate on everything and NIIT on capital gains.
+fn tax_rates(db: &Db, extra: &ExtraTaxRates) -> TaxRates {
+    let (income, short_term_gain, long_term_gain) = if let Some(ref rate) = db.get_tax_rate() {
+        (rate.income, rate.short_term_gain, rate.long_term_gain)
+    } else {
+        (0.3935f64, 0.3935f64, 0.22f64)
+    };
+    // the sys db and the config hold the rates as floats
+    let (income, short_term_gain, long_term_gain) = (
+        to_decimal(income),
+        to_decimal(short_term_gain),
+        to_decimal(long_term_gain),
+    );
+    let state = to_decimal(extra.state.unwrap_or_default());
+    let niit = to_decimal(extra.niit.unwrap_or_default());
+    TaxRates {
+        income: income + state,
+        short_term_gain: short_term_gain + state + niit,
+        long_term_gain: long_term_gain + state + niit,
+    }
+}
+
+/// Token amount in UI units, exact.
+fn ui_amount(token: MaybeToken, amount: u64) -> Decimal {
+    Decimal::from_i128_with_scale(amount as i128, token.decimals() as u32)
+}
+
+fn to_decimal(value: f64) -> Decimal {
+    Decimal::from_f64(value).unwrap_or_default()
+}
+
+/// Dollar amounts are kept exact and rounded to cents only for display.
+fn usd(value: Decimal) -> String {
+    let value = value.round_dp_with_strategy(2, RoundingStrategy::MidpointAwayFromZero);
+    let sign = if value.is_sign_negative() && !value.is_zero() {
+        "-"
+    } else {
+        ""
+    };
+    let digits = format!("{:.2}", value.abs());
+    let (dollars, cents) = digits.split_once('.').unwrap();
+    let mut separated = String::new();
+    for (i, digit) in dollars.chars().enumerate() {
+        if i > 0 && (dollars.len() - i) % 3 == 0 {
+            separated.push(',');
         }
+        separated.push(digit);
+    }
+    format!("{sign}{separated}.{cents}")
+}
+
+/// Tax on the net gain, a loss in one term offsetting the gain in the other.
+fn estimate_tax(short_gain: Decimal, long_gain: Decimal, rates: &TaxRates) -> Decimal {
+    let (long_term_gain_tax_rate, short_term_gain_tax_rate) =
+        (rates.long_term_gain, rates.short_term_gain);
+    if short_gain + long_gain <= Decimal::ZERO {
+        Decimal::ZERO
+    } else if short_gain > Decimal::ZERO && long_gain > Decimal::ZERO {
+        short_gain * short_term_gain_tax_rate + long_gain * long_term_gain_tax_rate
+    } else if long_gain > Decimal::ZERO {
+        (short_gain + long_gain) * long_term_gain_tax_rate
+    } else {
+        (short_gain + long_gain) * short_term_gain_tax_rate
     }
 }
 
+/// Amount, cost and unrealized short and long-term gain of held lots valued
+/// at `price` on `today`.
+fn lot_totals<'a>(
+    token: MaybeToken,
+    lots: impl Iterator<Item = &'a Lot>,
+    price: Decimal,
+    today: NaiveDate,
+) -> (u64, Decimal, Decimal, Decimal) {
+    lots.fold(
+        (0u64, Decimal::ZERO, Decimal::ZERO, Decimal::ZERO),
+        |acc, x| {
+            let amount = ui_amount(token, x.amount);
+            let basis = amount * x.acquisition.price();
+            let gain = amount * price - basis;
+            if holding::is_long_term(x.acquisition.when, today) {
+                (acc.0 + x.amount, acc.1 + basis, acc.2, acc.3 + gain)
+            } else {
+                (acc.0 + x.amount, acc.1 + basis, acc.2 + gain, acc.3)
+            }
+        },
+    )
+}
+
 #[component]
 pub fn Summary() -> Element {
     let selected_account = use_context::<GlobalState>().account.read().clone();
     let prices = use_context::<GlobalState>().prices.read().clone();
     let selected = use_context::<GlobalState>().selected;
     let db = DB.read().unwrap();
-    let (long_term_gain_tax_rate, short_term_gain_tax_rate) =
-        if let Some(ref rate) = db.get_tax_rate() {
-            (rate.long_term_gain, rate.short_term_gain)
-        } else {
-            (0.22f64, 0.3935f64)
-        };
+    let rates = tax_rates(&db, &use_context::<GlobalState>().extra_tax_rates.read());
     let accounts = db.get_accounts();
     let mut held_tokens = BTreeMap::<MaybeToken, u64>::default();
     for account in accounts {
@@ -947,18 +2426,31 @@ pub fn Summary() -> Element {
         let held_token = held_tokens.get_mut(&account.token).unwrap();
         *held_token += account.last_update_balance;
     }
-    let mut total = 0f64;
-    let mut selected_price = 0f64;
+    let mut total = Decimal::ZERO;
+    let mut selected_price = None;
+    let mut unpriced = vec![];
     for (t, a) in held_tokens.clone() {
-        let price = *prices.get(&t.to_string()).unwrap_or(&0f64);
+        let price = prices.get(&t.to_string()).map(|x| x.value);
         if let Some(ref account) = selected_account {
             if t == account.token {
                 selected_price = price;
             }
         }
-        total += price * t.ui_amount(a);
+        match price {
+            Some(price) => total += price * ui_amount(t, a),
+            None if a > 0 => unpriced.push(t.to_string()),
+            None => (),
+        }
     }
-    let mut summary = format!("total ${} (", total.separated_string_with_fixed_place(2));
+    let mut summary = if unpriced.is_empty() {
+        format!("total ${} (", usd(total))
+    } else {
+        format!(
+            "total ${} without {}, no price yet (",
+            usd(total),
+            unpriced.join(", ")
+        )
+    };
     for (i, (t, a)) in held_tokens.iter().enumerate() {
         if i == 0 {
             summary = format!("{summary}{}", t.format_amount(*a));
@@ -968,56 +2460,34 @@ pub fn Summary() -> Element {
     }
     summary = format!("{summary})");
     if !selected.read().is_empty() {
-        if let Some(account) = selected_account {
-            let selected_lots_value = account
-                .lots
-                .iter()
-                .filter(|x| selected.read().contains(&x.lot_number))
-                .fold(0u64, |acc, x| acc + x.amount);
-            let cost = account
-                .lots
-                .iter()
-                .filter(|x| selected.read().contains(&x.lot_number))
-                .fold(0f64, |acc, x| {
-                    acc + x.acquisition.price().to_f64().unwrap()
-                        * account.token.ui_amount(x.amount)
-                });
-            let today = chrono::Local::now().date_naive();
-            let (short_gain, long_gain) = account
-                .lots
-                .iter()
-                .filter(|x| selected.read().contains(&x.lot_number))
-                .fold((0f64, 0f64), |acc, x| {
-                    let amount = account.token.ui_amount(x.amount);
-                    let basis = amount * x.acquisition.price().to_f64().unwrap();
-                    let value = amount * selected_price;
-                    if today.signed_duration_since(x.acquisition.when).num_days() < 365 {
-                        (acc.0 + value - basis, acc.1)
-                    } else {
-                        (acc.0, acc.1 + value - basis)
-                    }
-                });
-            let value = account.token.ui_amount(selected_lots_value) * selected_price;
+        if let (Some(account), None) = (&selected_account, selected_price) {
+            summary = format!(
+                "{summary}, selected lots not valued, no {} price yet",
+                account.token
+            );
+        }
+        if let (Some(account), Some(selected_price)) = (selected_account, selected_price) {
+            let (selected_lots_value, cost, short_gain, long_gain) = lot_totals(
+                account.token,
+                account
+                    .lots
+                    .iter()
+                    .filter(|x| selected.read().contains(&x.lot_number)),
+                selected_price,
+                chrono::Local::now().date_naive(),
+            );
+            let value = ui_amount(account.token, selected_lots_value) * selected_price;
             let gain = short_gain + long_gain;
             summary = format!(
                 "{summary}, selected lots value {} = ${}, cost ${}, gain ${}",
                 account.token.format_amount(selected_lots_value),
-                value.separated_string_with_fixed_place(2),
-                cost.separated_string_with_fixed_place(2),
-                gain.separated_string_with_fixed_place(2),
+                usd(value),
+                usd(cost),
+                usd(gain),
             );
-            if gain > 0f64 {
-                let tax = if short_gain > 0f64 && long_gain > 0f64 {
-                    short_gain * short_term_gain_tax_rate + long_gain * long_term_gain_tax_rate
-                } else if long_gain > 0f64 {
-                    (short_gain + long_gain) * long_term_gain_tax_rate
-                } else {
-                    (short_gain + long_gain) * short_term_gain_tax_rate
-                };
-                summary = format!(
-                    "{summary}, tax ${}",
-                    tax.separated_string_with_fixed_place(2),
-                );
+            if gain > Decimal::ZERO {
+                let tax = estimate_tax(short_gain, long_gain, &rates);
+                summary = format!("{summary}, tax ${}", usd(tax));
             }
         }
     }
@@ -1043,326 +2513,1078 @@ pub fn Log() -> Element {
 }
 
 #[component]
-pub fn Disposed() -> Element {
-    let mut disposed_lots = DB.read().unwrap().disposed_lots().clone();
-    let mut state = use_context::<GlobalState>().state;
-
-    if let Some(ref sorting) = state.read().disposed_sorted {
-        match *sorting {
-            DisposedSorting::Lot(d) => {
-                disposed_lots.sort_by(|a, b| {
-                    if d {
-                        a.lot.lot_number.cmp(&b.lot.lot_number)
-                    } else {
-                        b.lot.lot_number.cmp(&a.lot.lot_number)
-                    }
-                });
-            }
-            DisposedSorting::SaleDate(d) => {
-                disposed_lots.sort_by(|a, b| {
-                    if d {
-                        a.when.cmp(&b.when)
-                    } else {
-                        b.when.cmp(&a.when)
-                    }
-                });
-            }
-            DisposedSorting::AcqDate(d) => {
-                disposed_lots.sort_by(|a, b| {
-                    if d {
-                        a.lot.acquisition.when.cmp(&b.lot.acquisition.when)
-                    } else {
-                        b.lot.acquisition.when.cmp(&a.lot.acquisition.when)
-                    }
-                });
-            }
-            DisposedSorting::Amount(d) => {
-                disposed_lots.sort_by(|a, b| {
-                    if d {
-                        a.lot.amount.cmp(&b.lot.amount)
-                    } else {
-                        b.lot.amount.cmp(&a.lot.amount)
-                    }
-                });
+pub fn ConfirmDialog() -> Element {
+    let mut pending = use_context::<GlobalState>().pending;
+    let preview = pending.read().clone();
+    if let Some(preview) = preview {
+        let operation = preview.operation;
+        let content = preview.report();
+        let confirm = move |_| {
+            let preview = preview.clone();
+            *pending.write() = None;
+            spawn(async move { do_operation(preview).await });
+        };
+        rsx! {
+            div { id: "confirm",
+                div { id: "confirm_dialog",
+                    h3 { "Confirm {operation}" }
+                    pre { "{content}" }
+                    button { onclick: confirm, "Confirm" }
+                    button { onclick: move |_| *pending.write() = None, "Cancel" }
+                }
             }
         }
+    } else {
+        rsx! {}
     }
+}
+
+#[component]
+pub fn Disposed() -> Element {
+    let state = use_context::<GlobalState>().state;
+    let disposed_table = use_context::<GlobalState>().disposed_table;
+    let disposed_selected = use_context::<GlobalState>().disposed_selected;
+    let wash_sales = get_wash_sales(&DB.read().unwrap());
+    let rows = filtered_disposed_lots(&state.read().disposed_filter)
+        .into_iter()
+        .map(|lot| DisposedRow {
+            wash_sale: wash_sales.get(&lot.lot.lot_number).cloned(),
+            lot,
+        })
+        .collect::<Vec<_>>();
 
     rsx! {
-        div { id: "disposed",
-            table {
-                thead {
-                    tr {
-                        th {
-                            onclick: move |_| {
-                                let sorted = state.read().disposed_sorted.clone();
-                                let mut v = true;
-                                if let Some(DisposedSorting::Lot(x)) = sorted {
-                                    v = !x;
-                                }
-                                state.write().disposed_sorted = Some(DisposedSorting::Lot(v));
-                            },
-                            "Lot"
-                        }
-                        th {
-                            onclick: move |_| {
-                                let sorted = state.read().disposed_sorted.clone();
-                                let mut v = true;
-                                if let Some(DisposedSorting::SaleDate(x)) = sorted {
-                                    v = !x;
-                                }
-                                state.write().disposed_sorted = Some(DisposedSorting::SaleDate(v));
-                            },
-                            "Sale Date"
-                        }
-                        th {
-                            onclick: move |_| {
-                                let sorted = state.read().disposed_sorted.clone();
-                                let mut v = true;
-                                if let Some(DisposedSorting::AcqDate(x)) = sorted {
-                                    v = !x;
-                                }
-                                state.write().disposed_sorted = Some(DisposedSorting::AcqDate(v));
-                            },
-                            "Acq Date"
-                        }
-                        th {
-                            onclick: move |_| {
-                                let sorted = state.read().disposed_sorted.clone();
-                                let mut v = true;
-                                if let Some(DisposedSorting::Amount(x)) = sorted {
-                                    v = !x;
-                                }
-                                state.write().disposed_sorted = Some(DisposedSorting::Amount(v));
-                            },
-                            "Amount"
-                        }
-                        th { "Income" }
-                        th { "Sale Price" }
-                        th { "Acq Price" }
-                        th { "Cap Gain" }
-                        th { "Term" }
+        DisposedFilters {}
+        Table {
+            id: "disposed",
+            columns: disposed_columns(),
+            rows,
+            state: disposed_table,
+            selected: disposed_selected,
+        }
+        DisposedSummary {}
+        DisposedExport {}
+    }
+}
+
+#[component]
+fn DisposedFilters() -> Element {
+    let mut state = use_context::<GlobalState>().state;
+    let filter = state.read().disposed_filter.clone();
+    let disposed = DB.read().unwrap().disposed_lots();
+    let years = disposed
+        .iter()
+        .map(|x| x.when.year())
+        .collect::<BTreeSet<_>>();
+    let tokens = disposed
+        .iter()
+        .map(|x| x.token.to_string())
+        .collect::<BTreeSet<_>>();
+    let kinds = disposed
+        .iter()
+        .map(|x| acquisition_kind_name(&x.lot.acquisition.kind))
+        .collect::<BTreeSet<_>>();
+    let from = filter.from.map(|x| x.to_string()).unwrap_or_default();
+    let to = filter.to.map(|x| x.to_string()).unwrap_or_default();
+    let parse_date = |value: String| NaiveDate::parse_from_str(&value, "%Y-%m-%d").ok();
+    rsx! {
+        div { id: "filters",
+            label { r#for: "filter_year", "tax year:" }
+            select {
+                id: "filter_year",
+                onchange: move |event| state.write().disposed_filter.year = event.value().parse::<i32>().ok(),
+                option { value: "", selected: filter.year.is_none(), "all" }
+                for year in years {
+                    option { value: "{year}", selected: filter.year == Some(year), "{year}" }
+                }
+            }
+            label { r#for: "filter_from", "from:" }
+            input {
+                id: "filter_from",
+                r#type: "date",
+                value: from,
+                oninput: move |event| state.write().disposed_filter.from = parse_date(event.value()),
+            }
+            label { r#for: "filter_to", "to:" }
+            input {
+                id: "filter_to",
+                r#type: "date",
+                value: to,
+                oninput: move |event| state.write().disposed_filter.to = parse_date(event.value()),
+            }
+            label { r#for: "filter_token", "token:" }
+            select {
+                id: "filter_token",
+                onchange: move |event| {
+                    let value = event.value();
+                    state.write().disposed_filter.token = if value.is_empty() { None } else { Some(value) };
+                },
+                option { value: "", selected: filter.token.is_none(), "all" }
+                for token in tokens {
+                    option {
+                        value: "{token}",
+                        selected: filter.token.as_ref() == Some(&token),
+                        "{token}"
                     }
                 }
-                tbody {
-                    for lot in disposed_lots {
-                        DisposedLotItem { lot: lot.clone() }
+            }
+            label { r#for: "filter_term", "term:" }
+            select {
+                id: "filter_term",
+                onchange: move |event| {
+                    state.write().disposed_filter.long_term = match event.value().as_str() {
+                        "S" => Some(false),
+                        "L" => Some(true),
+                        _ => None,
+                    };
+                },
+                option { value: "", selected: filter.long_term.is_none(), "all" }
+                option { value: "S", selected: filter.long_term == Some(false), "S" }
+                option { value: "L", selected: filter.long_term == Some(true), "L" }
+            }
+            label { r#for: "filter_kind", "acquired by:" }
+            select {
+                id: "filter_kind",
+                onchange: move |event| {
+                    let value = event.value();
+                    state.write().disposed_filter.kind = if value.is_empty() { None } else { Some(value) };
+                },
+                option { value: "", selected: filter.kind.is_none(), "all" }
+                for kind in kinds {
+                    option {
+                        value: "{kind}",
+                        selected: filter.kind.as_ref() == Some(&kind),
+                        "{kind}"
                     }
                 }
             }
+            button {
+                onclick: move |_| state.write().disposed_filter = DisposedFilter::default(),
+                "Clear"
+            }
         }
-        DisposedSummary {}
     }
 }
 
 #[component]
-fn DisposedLotItem(lot: DisposedLot) -> Element {
-    let lot_number = format!("{}", lot.lot.lot_number);
-    let amount = lot.token.format_amount(lot.lot.amount).to_string();
-    let acq_date = lot.lot.acquisition.when.to_string();
-    let acq_price = f64::try_from(lot.lot.acquisition.price()).unwrap();
-    let sale_date = lot.when.to_string();
-    let sale_price = f64::try_from(lot.price()).unwrap();
-    let income = match lot.lot.acquisition.kind {
-        LotAcquistionKind::NotAvailable | LotAcquistionKind::EpochReward { epoch: _, slot: _ } => {
-            acq_price * lot.token.ui_amount(lot.lot.amount)
-        }
-        _ => 0.0,
-    };
-    let gain = format!(
-        "{}",
-        (lot.token.ui_amount(lot.lot.amount) * (sale_price - acq_price))
-            .separated_string_with_fixed_place(2)
-    );
-    let acq_price = format!("${}", acq_price.separated_string_with_fixed_place(2));
-    let sale_price = format!("${}", sale_price.separated_string_with_fixed_place(2));
-    let income = format!("${}", income.separated_string_with_fixed_place(2));
-    let term = if lot
-        .when
-        .signed_duration_since(lot.lot.acquisition.when)
-        .num_days()
-        < 365
-    {
-        "S"
-    } else {
-        "L"
+fn DisposedExport() -> Element {
+    let selected = use_context::<GlobalState>().disposed_selected;
+    let mut year = use_signal(|| Local::now().year() - 1);
+    let mut dir = use_signal(|| std::env::var("HOME").unwrap_or_default());
+    let mut status = use_signal(|| None::<Result<String, String>>);
+    let mut export = move |selection: bool| {
+        let disposed = DB.read().unwrap().disposed_lots();
+        let (lots, title, name) = if selection {
+            (
+                disposed
+                    .into_iter()
+                    .filter(|x| selected.read().contains(&x.lot.lot_number))
+                    .collect::<Vec<_>>(),
+                "selected lots".to_string(),
+                "form8949-selected".to_string(),
+            )
+        } else {
+            (
+                disposed
+                    .into_iter()
+                    .filter(|x| x.when.year() == year())
+                    .collect::<Vec<_>>(),
+                format!("tax year {}", year()),
+                format!("form8949-{}", year()),
+            )
+        };
+        let wash_sales = get_wash_sales(&DB.read().unwrap());
+        let rows = lots
+            .iter()
+            .map(|x| tax::Form8949Row::new(x).with_wash_sale(wash_sales.get(&x.lot.lot_number)))
+            .collect::<Vec<_>>();
+        let dir = std::path::PathBuf::from(dir());
+        if !dir.is_dir() {
+            status.set(Some(Err(format!("{} is not a directory", dir.display()))));
+            return;
+        }
+        let path = export_path(&dir, &name);
+        status.set(Some(
+            std::fs::write(&path, tax::form_8949_csv(&rows, &title))
+                .map(|_| format!("Exported {} lots to {}", rows.len(), path.display()))
+                .map_err(|e| format!("Failed to write {}: {:?}", path.display(), e)),
+        ));
     };
+    rsx! {
+        div { id: "export",
+            label { r#for: "tax_year", "tax year:" }
+            input {
+                id: "tax_year",
+                r#type: "number",
+                value: "{year}",
+                oninput: move |event| {
+                    if let Ok(value) = event.value().parse::<i32>() {
+                        year.set(value);
+                    }
+                },
+            }
+            label { r#for: "export_dir", "to:" }
+            input {
+                id: "export_dir",
+                r#type: "text",
+                value: "{dir}",
+                oninput: move |event| dir.set(event.value()),
+            }
+            button { onclick: move |_| export(false), "Export Form 8949" }
+            button {
+                disabled: selected.read().is_empty(),
+                onclick: move |_| export(true),
+                "Export selected"
+            }
+            StatusMessage { status: status() }
+        }
+    }
+}
 
-    let mut selected = use_context::<GlobalState>().disposed_selected;
-    let kind = if selected.read().contains(&lot.lot.lot_number) {
-        "selected"
-    } else {
-        "regular"
-    };
-    let sorted = use_context::<GlobalState>()
+/// A path in `dir` for the export named `name` that doesn't replace an
+/// earlier export, numbered when the plain name is taken.
+fn export_path(dir: &std::path::Path, name: &str) -> std::path::PathBuf {
+    std::iter::once(format!("{name}.csv"))
+        .chain((1..).map(|n| format!("{name}-{n}.csv")))
+        .map(|x| dir.join(x))
+        .find(|x| !x.exists())
+        .unwrap()
+}
+
+#[derive(Clone, PartialEq)]
+struct DisposedRow {
+    pub lot: DisposedLot,
+    pub wash_sale: Option<tax::WashSale>,
+}
+
+impl TableRow for DisposedRow {
+    fn id(&self) -> usize {
+        self.lot.lot.lot_number
+    }
+
+    fn class(&self) -> &'static str {
+        if self.wash_sale.is_some() {
+            "wash"
+        } else {
+            "regular"
+        }
+    }
+
+    fn title(&self) -> String {
+        self.wash_sale
+            .as_ref()
+            .map(|x| {
+                format!(
+                    "Wash sale: ${} loss disallowed, added to the basis of lots {}",
+                    usd(x.disallowed_loss),
+                    x.replacements
+                        .iter()
+                        .map(|(lot, _)| lot.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            })
+            .unwrap_or_default()
+    }
+}
+
+impl DisposedRow {
+    fn amount(&self) -> Decimal {
+        ui_amount(self.lot.token, self.lot.lot.amount)
+    }
+
+    fn income(&self) -> Decimal {
+        match self.lot.lot.acquisition.kind {
+            LotAcquistionKind::NotAvailable
+            | LotAcquistionKind::EpochReward { epoch: _, slot: _ } => {
+                self.amount() * self.lot.lot.acquisition.price()
+            }
+            _ => Decimal::ZERO,
+        }
+    }
+
+    fn gain(&self) -> Decimal {
+        self.amount() * (self.lot.price() - self.lot.lot.acquisition.price())
+    }
+
+    fn term(&self) -> &'static str {
+        HoldingPeriod::new(self.lot.lot.acquisition.when).term(self.lot.when)
+    }
+}
+
+fn disposed_columns() -> Vec<Column<DisposedRow>> {
+    vec![
+        Column::new(
+            "lot",
+            "Lot",
+            |x| SortKey::Number(x.lot.lot.lot_number.into()),
+            |x| x.lot.lot.lot_number.to_string(),
+        )
+        .with_class("lot_number"),
+        Column::new(
+            "sale_date",
+            "Sale Date",
+            |x| SortKey::Date(x.lot.when),
+            |x| x.lot.when.to_string(),
+        )
+        .with_class("lot_date"),
+        Column::new(
+            "acq_date",
+            "Acq Date",
+            |x| SortKey::Date(x.lot.lot.acquisition.when),
+            |x| x.lot.lot.acquisition.when.to_string(),
+        )
+        .with_class("lot_date"),
+        Column::new(
+            "amount",
+            "Amount",
+            |x| SortKey::Number(x.amount()),
+            |x| x.lot.token.format_amount(x.lot.lot.amount).to_string(),
+        )
+        .with_class("lot_amount"),
+        Column::new(
+            "income",
+            "Income",
+            |x| SortKey::Number(x.income()),
+            |x| format!("${}", usd(x.income())),
+        ),
+        Column::new(
+            "sale_price",
+            "Sale Price",
+            |x| SortKey::Number(x.lot.price()),
+            |x| format!("${}", usd(x.lot.price())),
+        ),
+        Column::new(
+            "acq_price",
+            "Acq Price",
+            |x| SortKey::Number(x.lot.lot.acquisition.price()),
+            |x| format!("${}", usd(x.lot.lot.acquisition.price())),
+        ),
+        Column::new(
+            "gain",
+            "Cap Gain",
+            |x| SortKey::Number(x.gain()),
+            |x| usd(x.gain()),
+        ),
+        Column::new(
+            "term",
+            "Term",
+            |x| SortKey::Text(x.term().to_string()),
+            |x| x.term().to_string(),
+        )
+        .with_class("lot_term")
+        .with_tooltip(|x| {
+            let holding_period = HoldingPeriod::new(x.lot.lot.acquisition.when);
+            format!(
+                "held {} days, long-term from {}",
+                x.lot
+                    .when
+                    .signed_duration_since(x.lot.lot.acquisition.when)
+                    .num_days(),
+                holding_period.long_term_date()
+            )
+        }),
+    ]
+}
+
+/// SOL amount, proceeds, income and realized short and long-term gain of the
+/// disposed lots whose lot numbers satisfy `p`.
+fn disposed_totals(
+    lots: &[DisposedLot],
+    p: impl Fn(&usize) -> bool,
+) -> (u64, Decimal, Decimal, Decimal, Decimal) {
+    lots.iter().filter(|x| p(&x.lot.lot_number)).fold(
+        (
+            0u64,
+            Decimal::ZERO,
+            Decimal::ZERO,
+            Decimal::ZERO,
+            Decimal::ZERO,
+        ),
+        |acc, x| {
+            let amount = ui_amount(x.token, x.lot.amount);
+            let basis = amount * x.lot.acquisition.price();
+            let value = amount * x.price();
+            (
+                if x.token.is_sol() {
+                    acc.0 + x.lot.amount
+                } else {
+                    acc.0
+                },
+                acc.1 + value,
+                match &x.lot.acquisition.kind {
+                    LotAcquistionKind::NotAvailable
+                    | LotAcquistionKind::EpochReward { epoch: _, slot: _ } => acc.2 + basis,
+                    _ => acc.2,
+                },
+                if holding::is_long_term(x.lot.acquisition.when, x.when) {
+                    acc.3
+                } else {
+                    acc.3 + value - basis
+                },
+                if holding::is_long_term(x.lot.acquisition.when, x.when) {
+                    acc.4 + value - basis
+                } else {
+                    acc.4
+                },
+            )
+        },
+    )
+}
+
+#[component]
+pub fn DisposedSummary() -> Element {
+    let filter = use_context::<GlobalState>()
         .state
         .read()
-        .disposed_sorted
+        .disposed_filter
         .clone();
+    let disposed = filtered_disposed_lots(&filter);
+    let selected = use_context::<GlobalState>().disposed_selected;
+    let rates = tax_rates(
+        &DB.read().unwrap(),
+        &use_context::<GlobalState>().extra_tax_rates.read(),
+    );
+    let tax = |income: Decimal, short_gain: Decimal, long_gain: Decimal| {
+        income * rates.income + estimate_tax(short_gain, long_gain, &rates)
+    };
+    let (amount, value, income, short_gain, long_gain) = disposed_totals(&disposed, |_| true);
+    let mut summary = format!(
+        "Total disposed lots {} tokens {} value ${} income ${} short-term gain ${} long-term gain ${} tax ${}",
+        disposed.len(),
+        MaybeToken::SOL().format_amount(amount),
+        usd(value),
+        usd(income),
+        usd(short_gain),
+        usd(long_gain),
+        usd(tax(income, short_gain, long_gain)),
+    );
+    if !selected.read().is_empty() {
+        let (amount, value, income, short_gain, long_gain) =
+            disposed_totals(&disposed, |a| selected.read().contains(a));
+        summary = format!(
+            "{}\n      selected lots {} tokens {} value ${} income ${} short-term gain ${} long-term gain ${} tax ${}",
+            summary,
+            selected.read().len(),
+            MaybeToken::SOL().format_amount(amount),
+            usd(value),
+            usd(income),
+            usd(short_gain),
+            usd(long_gain),
+            usd(tax(income, short_gain, long_gain)),
+        );
+    }
+    let wash_sales = get_wash_sales(&DB.read().unwrap());
+    let wash_sales = disposed
+        .iter()
+        .filter_map(|x| wash_sales.get(&x.lot.lot_number))
+        .collect::<Vec<_>>();
+    if !wash_sales.is_empty() {
+        let disallowed_loss = wash_sales
+            .iter()
+            .map(|x| x.disallowed_loss)
+            .sum::<Decimal>();
+        let adjustments = wash_sales
+            .iter()
+            .flat_map(|x| x.replacements.iter())
+            .map(|(lot, adjustment)| format!("lot {lot} +${}", usd(*adjustment)))
+            .collect::<Vec<_>>()
+            .join(", ");
+        summary = format!(
+            "{}\n      wash sales {} disallowed loss ${} basis adjustments {}",
+            summary,
+            wash_sales.len(),
+            usd(disallowed_loss),
+            adjustments,
+        );
+    }
+    rsx! {
+        pre {"{summary}"}
+    }
+}
 
-    let select_lot = move |event: Event<MouseData>| {
-        let lot = lot.lot.lot_number;
-        let mut selected = selected.write();
-        let modifiers = event.data().modifiers();
-        if modifiers.shift() {
-            let mut disposed_lots = DB.read().unwrap().disposed_lots().clone();
-            if let Some(sorting) = sorted.clone() {
-                match sorting {
-                    DisposedSorting::Lot(d) => {
-                        disposed_lots.sort_by(|a, b| {
-                            if d {
-                                a.lot.lot_number.cmp(&b.lot.lot_number)
-                            } else {
-                                b.lot.lot_number.cmp(&a.lot.lot_number)
-                            }
-                        });
+#[derive(Clone, PartialEq)]
+struct SaleEstimate {
+    pub lots: Vec<String>,
+    pub proceeds: Decimal,
+    pub basis: Decimal,
+    pub short_gain: Decimal,
+    pub long_gain: Decimal,
+    pub tax: Decimal,
+}
+
+#[allow(clippy::too_many_arguments)]
+fn simulate_sale(
+    token: MaybeToken,
+    lots: &[Lot],
+    selected: &BTreeSet<usize>,
+    amount: u64,
+    price: Decimal,
+    date: NaiveDate,
+    method: LotMethod,
+    rates: &TaxRates,
+) -> Result<SaleEstimate, String> {
+    // the selection names the lots for specific ID only, the other methods
+    // pick from all the lots, and lots of other tokens and accounts left
+    // selected don't count
+    let selected = match method {
+        LotMethod::SpecificId => lots
+            .iter()
+            .map(|x| x.lot_number)
+            .filter(|x| selected.contains(x))
+            .collect(),
+        _ => BTreeSet::new(),
+    };
+    let used = select_lots(lots, &selected, amount, method)?;
+    let (mut basis, mut short_gain, mut long_gain) = (Decimal::ZERO, Decimal::ZERO, Decimal::ZERO);
+    for (lot, amount) in &used {
+        let amount = ui_amount(token, *amount);
+        let lot_basis = amount * lot.acquisition.price();
+        let gain = amount * price - lot_basis;
+        basis += lot_basis;
+        if holding::is_long_term(lot.acquisition.when, date) {
+            long_gain += gain;
+        } else {
+            short_gain += gain;
+        }
+    }
+    Ok(SaleEstimate {
+        lots: describe_lots(token, &used),
+        proceeds: ui_amount(token, amount) * price,
+        basis,
+        short_gain,
+        long_gain,
+        tax: estimate_tax(short_gain, long_gain, rates),
+    })
+}
+
+/// Compares selling an amount of a token under every lot selection method.
+/// Uses the lots of the selected account when it holds the token, otherwise
+/// the lots of all accounts.
+#[component]
+pub fn Simulator() -> Element {
+    let account = use_context::<GlobalState>().account.read().clone();
+    let selected = use_context::<GlobalState>().selected.read().clone();
+    let prices = use_context::<GlobalState>().prices.read().clone();
+    let mut token = use_signal(|| {
+        account
+            .as_ref()
+            .map(|x| x.token.to_string())
+            .unwrap_or_else(|| MaybeToken::SOL().to_string())
+    });
+    let mut amount = use_signal(|| None::<f64>);
+    let mut price = use_signal(|| None::<Decimal>);
+    let mut date = use_signal(|| Local::now().date_naive());
+
+    let db = DB.read().unwrap();
+    let accounts = db.get_accounts();
+    let tokens = accounts
+        .iter()
+        .map(|x| x.token.to_string())
+        .collect::<BTreeSet<_>>();
+    let held = accounts
+        .iter()
+        .filter(|x| x.token.to_string() == token())
+        .collect::<Vec<_>>();
+    let (lots, source) = match account {
+        Some(ref account) if account.token.to_string() == token() => {
+            (account.lots.clone(), account.description.clone())
+        }
+        _ => (
+            held.iter().flat_map(|x| x.lots.iter().cloned()).collect(),
+            format!("all {} accounts", token()),
+        ),
+    };
+    let rates = tax_rates(&db, &use_context::<GlobalState>().extra_tax_rates.read());
+    let current_price = prices.get(&token()).map(|x| x.value);
+    let sale_price = price().or(current_price);
+    let estimates = match (held.first(), amount(), sale_price) {
+        (Some(account), Some(amount), Some(sale_price)) if amount > 0. => LotMethod::ALL
+            .into_iter()
+            .map(|method| {
+                (
+                    method,
+                    simulate_sale(
+                        account.token,
+                        &lots,
+                        &selected,
+                        account.token.amount(amount),
+                        sale_price,
+                        date(),
+                        method,
+                        &rates,
+                    ),
+                )
+            })
+            .collect::<Vec<_>>(),
+        _ => vec![],
+    };
+    let cheapest = estimates
+        .iter()
+        .filter_map(|(method, x)| x.as_ref().ok().map(|x| (method, x.tax)))
+        .min_by_key(|x| x.1)
+        .map(|x| *x.0);
+    let money = |f: fn(&SaleEstimate) -> Decimal| {
+        estimates
+            .iter()
+            .map(|(_, x)| match x {
+                Ok(x) => format!("${}", usd(f(x))),
+                Err(_) => String::new(),
+            })
+            .collect::<Vec<_>>()
+    };
+    let rows = [
+        ("Proceeds", money(|x| x.proceeds)),
+        ("Basis", money(|x| x.basis)),
+        ("Short-term gain", money(|x| x.short_gain)),
+        ("Long-term gain", money(|x| x.long_gain)),
+        ("Tax", money(|x| x.tax)),
+        ("Net", money(|x| x.proceeds - x.tax)),
+    ];
+    rsx! {
+        div { id: "simulator",
+            div {
+                label { r#for: "sim_token", "token:" }
+                select {
+                    id: "sim_token",
+                    onchange: move |event| token.set(event.value()),
+                    for t in tokens {
+                        option { value: "{t}", selected: t == token(), "{t}" }
                     }
-                    DisposedSorting::SaleDate(d) => {
-                        disposed_lots.sort_by(|a, b| {
-                            if d {
-                                a.when.cmp(&b.when)
-                            } else {
-                                b.when.cmp(&a.when)
+                }
+                label { r#for: "sim_amount", "amount:" }
+                input {
+                    id: "sim_amount",
+                    oninput: move |event| amount.set(event.value().parse::<f64>().ok()),
+                }
+                label { r#for: "sim_price", "price:" }
+                input {
+                    id: "sim_price",
+                    placeholder: current_price.map(|x| x.to_string()).unwrap_or_default(),
+                    oninput: move |event| price.set(event.value().parse::<Decimal>().ok()),
+                }
+                label { r#for: "sim_date", "sale date:" }
+                input {
+                    id: "sim_date",
+                    r#type: "date",
+                    value: "{date}",
+                    oninput: move |event| {
+                        if let Ok(value) = NaiveDate::parse_from_str(&event.value(), "%Y-%m-%d") {
+                            date.set(value);
+                        }
+                    },
+                }
+                " lots of {source}"
+            }
+            if !estimates.is_empty() {
+                table {
+                    tr {
+                        th {}
+                        for (method , _) in estimates.iter() {
+                            th { class: if Some(*method) == cheapest { "selected" } else { "regular" },
+                                "{method}"
                             }
-                        });
+                        }
                     }
-                    DisposedSorting::AcqDate(d) => {
-                        disposed_lots.sort_by(|a, b| {
-                            if d {
-                                a.lot.acquisition.when.cmp(&b.lot.acquisition.when)
-                            } else {
-                                b.lot.acquisition.when.cmp(&a.lot.acquisition.when)
+                    for (name , values) in rows {
+                        tr {
+                            td { "{name}" }
+                            for value in values {
+                                td { "{value}" }
                             }
-                        });
+                        }
                     }
-                    DisposedSorting::Amount(d) => {
-                        disposed_lots.sort_by(|a, b| {
-                            if d {
-                                a.lot.amount.cmp(&b.lot.amount)
-                            } else {
-                                b.lot.amount.cmp(&a.lot.amount)
+                    tr {
+                        td { "Lots" }
+                        for (_ , estimate) in estimates.iter() {
+                            td { class: "validator",
+                                {
+                                    match estimate {
+                                        Ok(x) => x.lots.join(", "),
+                                        Err(e) => e.clone(),
+                                    }
+                                }
                             }
-                        });
+                        }
                     }
                 }
+            } else {
+                p { "Enter an amount and a price to compare the lot selection methods" }
             }
-            let mut sel_end = 0;
-            let mut sel_beg = 0;
-            for (i, l) in disposed_lots.iter().enumerate() {
-                if l.lot.lot_number == lot {
-                    sel_end = i;
-                } else if selected.contains(&l.lot.lot_number) {
-                    sel_beg = i;
+        }
+    }
+}
+
+fn percent(rate: Option<f64>) -> String {
+    rate.map(|x| format!("{}", (x * 10000.).round() / 100.))
+        .unwrap_or_default()
+}
+
+fn parse_percent(value: &str) -> Result<Option<f64>, String> {
+    if value.trim().is_empty() {
+        return Ok(None);
+    }
+    match value.trim().parse::<f64>() {
+        Ok(x) if (0. ..=100.).contains(&x) => Ok(Some(x / 100.)),
+        Ok(_) => Err(format!("Rate {value} is not between 0 and 100%")),
+        Err(_) => Err(format!("Invalid rate {value}")),
+    }
+}
+
+/// Edits the federal rates stored in the sys db and the state and NIIT
+/// rates kept in the sys-ui config.
+#[component]
+pub fn Settings() -> Element {
+    // keyed by profile so that switching profiles reloads the form with the
+    // rates of the new sys db
+    let profile = use_context::<GlobalState>().state.read().profile.clone();
+    rsx! {
+        TaxRatesForm { key: "{profile}" }
+    }
+}
+
+#[component]
+fn TaxRatesForm() -> Element {
+    let read_only = use_context::<GlobalState>().read_only.read().is_some();
+    let mut extra_tax_rates = use_context::<GlobalState>().extra_tax_rates;
+    let stored = DB.read().unwrap().get_tax_rate();
+    let mut income = use_signal(|| percent(stored.as_ref().map(|x| x.income)));
+    let mut short_term = use_signal(|| percent(stored.as_ref().map(|x| x.short_term_gain)));
+    let mut long_term = use_signal(|| percent(stored.as_ref().map(|x| x.long_term_gain)));
+    let mut state = use_signal(|| percent(extra_tax_rates.read().state));
+    let mut niit = use_signal(|| percent(extra_tax_rates.read().niit));
+    let mut status = use_signal(|| None::<Result<String, String>>);
+    let effective = tax_rates(&DB.read().unwrap(), &extra_tax_rates.read());
+    let effective = format!(
+        "Effective rates: income {}%, short-term gain {}%, long-term gain {}%{}",
+        (effective.income * Decimal::ONE_HUNDRED).normalize(),
+        (effective.short_term_gain * Decimal::ONE_HUNDRED).normalize(),
+        (effective.long_term_gain * Decimal::ONE_HUNDRED).normalize(),
+        if stored.is_none() {
+            ", the sys db has no rates and the defaults are used"
+        } else {
+            ""
+        },
+    );
+    let save = move |_| {
+        let result = (|| {
+            let rates = (
+                parse_percent(&income.read())?,
+                parse_percent(&short_term.read())?,
+                parse_percent(&long_term.read())?,
+            );
+            let extra = ExtraTaxRates {
+                state: parse_percent(&state.read())?,
+                niit: parse_percent(&niit.read())?,
+            };
+            let tax_rate = match rates {
+                (Some(income), Some(short_term_gain), Some(long_term_gain)) => {
+                    if read_only {
+                        return Err("The sys db is read-only".to_string());
+                    }
+                    Some(TaxRate {
+                        income,
+                        short_term_gain,
+                        long_term_gain,
+                    })
                 }
+                (None, None, None) => None,
+                _ => return Err("Enter all three federal rates".to_string()),
+            };
+            // everything is checked before either is written, the config
+            // first as it's the one more likely to fail
+            let mut config = Config::load()?;
+            config.extra_tax_rates = extra;
+            config.save()?;
+            extra_tax_rates.set(extra);
+            if let Some(tax_rate) = tax_rate {
+                DB.write().unwrap().set_tax_rate(tax_rate).map_err(|e| {
+                    format!(
+                        "Saved the state and NIIT rates, failed to save the federal rates: {e:?}"
+                    )
+                })?;
             }
-            if sel_beg > sel_end {
-                std::mem::swap(&mut sel_beg, &mut sel_end);
+            Ok("Saved".to_string())
+        })();
+        status.set(Some(result));
+    };
+    rsx! {
+        div { id: "settings",
+            h3 { "Tax rates, in percent" }
+            div {
+                label { r#for: "income_rate", "Income (sys db)" }
+                input {
+                    id: "income_rate",
+                    value: "{income}",
+                    oninput: move |event| income.set(event.value()),
+                }
             }
-            for i in sel_beg..=sel_end {
-                selected.insert(disposed_lots[i].lot.lot_number);
+            div {
+                label { r#for: "short_term_rate", "Short-term capital gain (sys db)" }
+                input {
+                    id: "short_term_rate",
+                    value: "{short_term}",
+                    oninput: move |event| short_term.set(event.value()),
+                }
             }
-        } else if modifiers.meta() {
-            if selected.contains(&lot) {
-                selected.remove(&lot);
-            } else {
-                selected.insert(lot);
+            div {
+                label { r#for: "long_term_rate", "Long-term capital gain (sys db)" }
+                input {
+                    id: "long_term_rate",
+                    value: "{long_term}",
+                    oninput: move |event| long_term.set(event.value()),
+                }
             }
-        } else {
-            selected.clear();
-            selected.insert(lot);
+            div {
+                label { r#for: "state_rate", "State income tax, optional" }
+                input {
+                    id: "state_rate",
+                    value: "{state}",
+                    oninput: move |event| state.set(event.value()),
+                }
+            }
+            div {
+                label { r#for: "niit_rate", "Net investment income tax, optional" }
+                input {
+                    id: "niit_rate",
+                    value: "{niit}",
+                    oninput: move |event| niit.set(event.value()),
+                }
+            }
+            p { "{effective}" }
+            button { onclick: save, "Save" }
+            StatusMessage { status: status() }
         }
-    };
+    }
+}
+
+const SNAPSHOT_INTERVAL_SECS: i64 = 60 * 60;
+
+/// Values the held tokens at the current prices. Tokens without a price
+/// yet are listed as unpriced and left out of the totals.
+fn portfolio_snapshot(
+    db: &Db,
+    prices: &BTreeMap<String, Price>,
+) -> Result<PortfolioSnapshot, String> {
+    let mut tokens = BTreeMap::<String, TokenHolding>::default();
+    let mut unpriced = BTreeSet::new();
+    for account in db.get_accounts() {
+        let Some(price) = prices.get(&account.token.to_string()).map(|x| x.value) else {
+            if account.last_update_balance > 0 {
+                unpriced.insert(account.token.to_string());
+            }
+            continue;
+        };
+        let amount = ui_amount(account.token, account.last_update_balance);
+        let cost_basis = account.lots.iter().fold(Decimal::ZERO, |acc, x| {
+            acc + x.acquisition.price() * ui_amount(account.token, x.amount)
+        });
+        let holding = tokens
+            .entry(account.token.to_string())
+            .or_insert(TokenHolding {
+                amount: 0.,
+                value: 0.,
+                cost_basis: 0.,
+            });
+        // the snapshots only feed the chart, they're stored as floats
+        holding.amount += amount.to_f64().unwrap();
+        holding.value += (amount * price).to_f64().unwrap();
+        holding.cost_basis += cost_basis.to_f64().unwrap();
+    }
+    if tokens.is_empty() && !unpriced.is_empty() {
+        return Err(format!(
+            "no price yet for {}",
+            unpriced.into_iter().collect::<Vec<_>>().join(", ")
+        ));
+    }
+    Ok(PortfolioSnapshot {
+        when: Utc::now(),
+        value: tokens.values().map(|x| x.value).sum(),
+        cost_basis: tokens.values().map(|x| x.cost_basis).sum(),
+        tokens,
+        unpriced: unpriced.into_iter().collect(),
+    })
+}
 
+#[component]
+pub fn History() -> Element {
+    let profile = use_context::<GlobalState>().state.read().profile.clone();
+    let range = use_signal(|| ChartRange::Month);
+    let snapshots =
+        PortfolioHistory::new(CONFIG.price_history_dir(), &profile).snapshots(range().since());
+    let series = |f: fn(&PortfolioSnapshot) -> f64| {
+        snapshots
+            .iter()
+            .map(|x| (x.when.timestamp(), f(x)))
+            .collect::<Vec<_>>()
+    };
+    let series = vec![
+        ("net worth".to_string(), series(|x| x.value)),
+        ("cost basis".to_string(), series(|x| x.cost_basis)),
+        (
+            "unrealized gain".to_string(),
+            series(|x| x.unrealized_gain()),
+        ),
+    ];
     rsx! {
-        tr {
-            class: kind,
-            onclick: select_lot,
-            td { class: "lot_number", "{lot_number}" }
-            td { class: "lot_date", "{sale_date}" }
-            td { class: "lot_date", "{acq_date}" }
-            td { class: "lot_amount", "{amount}" }
-            td { "{income}" }
-            td { "{sale_price}" }
-            td { "{acq_price}" }
-            td { "{gain}" }
-            td { class: "lot_term", "{term}" }
+        div { id: "history",
+            div { class: "chart",
+                ChartRanges { range }
+                LineChart { series }
+            }
+            if let Some(last) = snapshots.last() {
+                table {
+                    tr {
+                        th { "Token" }
+                        th { "Amount" }
+                        th { "Value" }
+                        th { "Cost basis" }
+                        th { "Unrealized gain" }
+                    }
+                    for (token , holding) in last.tokens.clone() {
+                        tr {
+                            td { "{token}" }
+                            td { "{holding.amount}" }
+                            td { {format!("${}", holding.value.separated_string_with_fixed_place(2))} }
+                            td { {format!("${}", holding.cost_basis.separated_string_with_fixed_place(2))} }
+                            td {
+                                {
+                                    format!(
+                                        "${}",
+                                        (holding.value - holding.cost_basis).separated_string_with_fixed_place(2),
+                                    )
+                                }
+                            }
+                        }
+                    }
+                    tr {
+                        td { "Total" }
+                        td {}
+                        td { {format!("${}", last.value.separated_string_with_fixed_place(2))} }
+                        td { {format!("${}", last.cost_basis.separated_string_with_fixed_place(2))} }
+                        td { {format!("${}", last.unrealized_gain().separated_string_with_fixed_place(2))} }
+                    }
+                }
+                if !last.unpriced.is_empty() {
+                    p { {format!("Not valued, no price at the time: {}", last.unpriced.join(", "))} }
+                }
+            }
         }
     }
 }
 
+#[derive(Clone, PartialEq)]
+struct ValidatorInfo {
+    pub vote_pubkey: String,
+    pub node_pubkey: String,
+    pub commission: u8,
+    pub activated_stake: u64,
+    pub last_vote: u64,
+    pub delinquent: bool,
+    pub delegated: usize,
+}
+
 #[component]
-pub fn DisposedSummary() -> Element {
-    fn aggregate(lots: &Vec<DisposedLot>, p: impl Fn(&usize) -> bool) -> (u64, f64, f64, f64, f64) {
-        lots.iter().filter(|x| p(&x.lot.lot_number)).fold(
-            (0u64, 0f64, 0f64, 0f64, 0f64),
-            |acc, x| {
-                let amount = x.token.ui_amount(x.lot.amount);
-                let basis = amount * x.lot.acquisition.price().to_f64().unwrap();
-                let value = amount * x.price().to_f64().unwrap();
-                (
-                    if x.token.is_sol() {
-                        acc.0 + x.lot.amount
-                    } else {
-                        acc.0
-                    },
-                    acc.1 + amount * f64::try_from(x.price()).unwrap(),
-                    match &x.lot.acquisition.kind {
-                        LotAcquistionKind::NotAvailable
-                        | LotAcquistionKind::EpochReward { epoch: _, slot: _ } => {
-                            acc.2 + amount * f64::try_from(x.lot.acquisition.price()).unwrap()
-                        }
-                        _ => acc.2,
-                    },
-                    if x.when
-                        .signed_duration_since(x.lot.acquisition.when)
-                        .num_days()
-                        < 365
-                    {
-                        acc.3 + value - basis
-                    } else {
-                        acc.3
-                    },
-                    if x.when
-                        .signed_duration_since(x.lot.acquisition.when)
-                        .num_days()
-                        < 365
+pub fn Validators() -> Element {
+    let mut search = use_signal(String::new);
+    let validators = use_resource(move || async move {
+        // the rpc calls block, so they run on their own thread with
+        // neither lock held
+        let url = RPC.read().unwrap().default().url();
+        let accounts = DB.read().unwrap().get_accounts();
+        tokio::task::spawn_blocking(move || {
+            get_validators(&RpcClient::new(url), &accounts).map_err(|e| e.to_string())
+        })
+        .await
+        .unwrap_or_else(|e| Err(e.to_string()))
+    });
+    let validators = validators.read();
+    let validators = match validators.as_ref() {
+        None => {
+            return rsx! {
+                div { id: "validators", "Loading validators" }
+            }
+        }
+        Some(Err(e)) => {
+            return rsx! {
+                div { id: "validators", "Failed to get vote accounts: {e}" }
+            }
+        }
+        Some(Ok(validators)) => validators,
+    };
+    let pattern = search.read().to_lowercase();
+    let recipient = use_context::<GlobalState>().state.read().recipient.clone();
+    rsx! {
+        div { id: "validator_search",
+            label { r#for: "search", "search:" }
+            input {
+                id: "search",
+                name: "search",
+                value: "{search}",
+                oninput: move |event| search.set(event.value()),
+            }
+        }
+        div { id: "validators",
+            table {
+                thead {
+                    tr {
+                        th { "Vote Account" }
+                        th { "Identity" }
+                        th { "Commission" }
+                        th { "Active Stake" }
+                        th { "Last Vote" }
+                        th { "Delinquent" }
+                        th { "Our Accounts" }
+                    }
+                }
+                tbody {
+                    for validator in validators
+                        .iter()
+                        .filter(|x| {
+                            pattern.is_empty() || x.vote_pubkey.to_lowercase().contains(&pattern)
+                                || x.node_pubkey.to_lowercase().contains(&pattern)
+                        })
                     {
-                        acc.4
-                    } else {
-                        acc.4 + value - basis
-                    },
-                )
-            },
-        )
-    }
-    let disposed = DB.read().unwrap().disposed_lots();
-    let selected = use_context::<GlobalState>().disposed_selected;
-    let (amount, value, income, short_gain, long_gain) = aggregate(&disposed, |_| true);
-    let mut summary = format!(
-        "Total disposed lots {} tokens {} value ${} income ${} short-term gain ${} long-term gain ${}",
-        disposed.len(),
-        MaybeToken::SOL().format_amount(amount),
-        value.separated_string_with_fixed_place(2),
-        income.separated_string_with_fixed_place(2),
-        short_gain.separated_string_with_fixed_place(2),
-        long_gain.separated_string_with_fixed_place(2),
-    );
-    if !selected.read().is_empty() {
-        let (amount, value, income, short_gain, long_gain) =
-            aggregate(&disposed, |a| selected.read().contains(a));
-        summary = format!(
-            "{}\n      selected lots {} tokens {} value ${} income ${} short-term gain ${} long-term gain ${}",
-            summary,
-            selected.read().len(),
-            MaybeToken::SOL().format_amount(amount),
-            value.separated_string_with_fixed_place(2),
-            income.separated_string_with_fixed_place(2),
-            short_gain.separated_string_with_fixed_place(2),
-            long_gain.separated_string_with_fixed_place(2),
-        );
+                        ValidatorItem {
+                            validator: validator.clone(),
+                            selected: recipient.as_ref() == Some(&validator.vote_pubkey),
+                        }
+                    }
+                }
+            }
+        }
     }
+}
+
+#[component]
+fn ValidatorItem(validator: ValidatorInfo, selected: bool) -> Element {
+    let kind = if selected { "selected" } else { "regular" };
+    let stake = MaybeToken::SOL().format_amount(validator.activated_stake);
+    let delinquent = if validator.delinquent { "yes" } else { "" };
+    let delegated = if validator.delegated > 0 {
+        validator.delegated.to_string()
+    } else {
+        String::new()
+    };
     rsx! {
-        pre {"{summary}"}
+        tr {
+            class: kind,
+            onclick: move |_| {
+                let mut state = use_context::<GlobalState>().state;
+                state.write().recipient = Some(validator.vote_pubkey.clone());
+                use_context::<GlobalState>()
+                    .log
+                    .set(Some(format!("Delegation target {}", validator.vote_pubkey)));
+                navigator().push(Route::Main {});
+            },
+            td { class: "validator", "{validator.vote_pubkey}" }
+            td { class: "validator", "{validator.node_pubkey}" }
+            td { "{validator.commission}%" }
+            td { "{stake}" }
+            td { "{validator.last_vote}" }
+            td { class: "lot_term", "{delinquent}" }
+            td { "{delegated}" }
+        }
     }
 }
 
@@ -1400,50 +3622,432 @@ macro_rules! make_signer {
                     return;
                 }
             };
-            (signer.expect("signer"), address.expect("address"))
+            (signer.expect("signer"), address.expect("address"))
+        }
+    }
+}
+
+fn signer_pubkey(signer: &str) -> Result<Pubkey, String> {
+    if let Err(e) = is_valid_signer(signer) {
+        return Err(format!("Invalid signer {signer}: {e}"));
+    }
+    let signer = signer.to_string();
+    let arg_matches = make_arg_matches!("by", signer, is_valid_signer);
+    let mut wallet_manager = None;
+    match pubkey_of_signer(&arg_matches, "by", &mut wallet_manager) {
+        Ok(Some(pubkey)) => Ok(pubkey),
+        Ok(None) => Err(format!("Invalid signer {signer}")),
+        Err(e) => Err(format!("Invalid signer {signer}: {e:?}")),
+    }
+}
+
+fn recipient_pubkey(recipient: &Option<String>, what: &str) -> Result<Pubkey, String> {
+    let recipient = recipient.clone().ok_or_else(|| format!("Enter {what}"))?;
+    if is_valid_pubkey(&recipient).is_err() {
+        return Err(format!("Invalid {what} {recipient}"));
+    }
+    let arg_matches = make_arg_matches!("to", recipient, is_valid_pubkey);
+    pubkey_of(&arg_matches, "to").ok_or_else(|| format!("Invalid {what} {recipient}"))
+}
+
+fn describe_instruction(instruction: &Instruction) -> String {
+    let decoded = if instruction.program_id == solana_sdk::stake::program::id() {
+        bincode::deserialize::<solana_sdk::stake::instruction::StakeInstruction>(&instruction.data)
+            .map(|x| format!("Stake {x:?}"))
+            .ok()
+    } else if instruction.program_id == system_program::id() {
+        bincode::deserialize::<solana_sdk::system_instruction::SystemInstruction>(&instruction.data)
+            .map(|x| format!("System {x:?}"))
+            .ok()
+    } else {
+        None
+    };
+    let mut description = decoded.unwrap_or_else(|| {
+        format!(
+            "Program {} ({} bytes of data)",
+            instruction.program_id,
+            instruction.data.len()
+        )
+    });
+    for meta in instruction.accounts.iter() {
+        description = format!(
+            "{description}\n       {}{}{}",
+            meta.pubkey,
+            if meta.is_signer { " signer" } else { "" },
+            if meta.is_writable { " writable" } else { "" },
+        );
+    }
+    description
+}
+
+fn simulate_instructions(
+    rpc_client: &RpcClient,
+    instructions: &[Instruction],
+    fee_payer: &Pubkey,
+) -> Result<(u64, String), Box<dyn std::error::Error>> {
+    let message = Message::new(instructions, Some(fee_payer));
+    let mut transaction = Transaction::new_unsigned(message);
+    transaction.message.recent_blockhash = rpc_client.get_latest_blockhash()?;
+    let fee = rpc_client.get_fee_for_message(&transaction.message)?;
+    let addresses = transaction.message.account_keys.clone();
+    let config = solana_client::rpc_config::RpcSimulateTransactionConfig {
+        commitment: Some(rpc_client.commitment()),
+        accounts: Some(
+            solana_client::rpc_config::RpcSimulateTransactionAccountsConfig {
+                encoding: None,
+                addresses: addresses.iter().map(|x| x.to_string()).collect(),
+            },
+        ),
+        ..solana_client::rpc_config::RpcSimulateTransactionConfig::default()
+    };
+    let pre_accounts = rpc_client.get_multiple_accounts(&addresses)?;
+    let simulation_result = rpc_client
+        .simulate_transaction_with_config(&transaction, config)?
+        .value;
+    let mut simulation = match simulation_result.err {
+        Some(err) => format!("  Failure: {err}\n"),
+        None => "  Success\n".to_string(),
+    };
+    if let Some(units) = simulation_result.units_consumed {
+        simulation = format!("{simulation}  Compute units: {units}\n");
+    }
+    if let Some(post_accounts) = simulation_result.accounts {
+        simulation = format!("{simulation}  Balance changes:\n");
+        for ((address, pre), post) in addresses.iter().zip(pre_accounts).zip(post_accounts) {
+            let pre = pre.map(|x| x.lamports).unwrap_or_default();
+            let post = post.map(|x| x.lamports).unwrap_or_default();
+            if pre != post {
+                simulation = format!(
+                    "{simulation}    {address}: {} -> {}\n",
+                    MaybeToken::SOL().format_amount(pre),
+                    MaybeToken::SOL().format_amount(post),
+                );
+            }
+        }
+    }
+    for log in simulation_result.logs.unwrap_or_default() {
+        simulation = format!("{simulation}  {log}\n");
+    }
+    Ok((fee, simulation))
+}
+
+fn make_preview(
+    operation: Operation,
+    account: Option<TrackedAccount>,
+    state: &State,
+    selected: &BTreeSet<usize>,
+) -> Result<Preview, String> {
+    let Some(account) = account else {
+        return Err(format!("Select account to {operation}"));
+    };
+    let authority = state
+        .authority
+        .clone()
+        .ok_or_else(|| format!("Enter authority keypair for account to {operation}"))?;
+    let fee_payer = signer_pubkey(&authority)?;
+    let selected = account_selection(&account, selected);
+    let lots = account
+        .lots
+        .iter()
+        .filter(|x| selected.contains(&x.lot_number))
+        .collect::<Vec<_>>();
+    // an amount typed is taken from the selected lots, or from the lots the
+    // method picks when none are selected
+    let amount = if state.amount.unwrap_or_default() > 0. {
+        account.token.amount(state.amount.unwrap())
+    } else {
+        lots.iter().fold(0, |acc, x| acc + x.amount)
+    };
+    if amount == 0
+        && matches!(
+            operation,
+            Operation::Split | Operation::Withdraw | Operation::Swap
+        )
+    {
+        return Err(format!("Select lots or enter amount to {operation}"));
+    }
+    let moves_lots = matches!(
+        operation,
+        Operation::Split
+            | Operation::Withdraw
+            | Operation::Swap
+            | Operation::NewStake
+            | Operation::MoveStake
+            | Operation::MoveLamports
+    );
+    let lots = if moves_lots {
+        let used = select_lots(&account.lots, &selected, amount, state.lot_method)?;
+        describe_lots(account.token, &used)
+    } else {
+        vec![]
+    };
+    let mut recipient = state.recipient.clone();
+    let mut new_account = None;
+    let instructions = match operation {
+        Operation::Split => {
+            let to_keypair = match recipient {
+                Some(ref r) => {
+                    // clap exits the process on a value its validator rejects
+                    if is_keypair(r).is_err() {
+                        return Err(format!("Invalid keypair for new account {r}"));
+                    }
+                    let arg_matches = make_arg_matches!("to", r, is_keypair);
+                    keypair_of(&arg_matches, "to")
+                        .ok_or_else(|| format!("Invalid keypair for new account {r}"))?
+                }
+                None => {
+                    let to_keypair = Keypair::new();
+                    recipient = Some(format!("{} (new random keypair)", to_keypair.pubkey()));
+                    to_keypair
+                }
+            };
+            let to_address = to_keypair.pubkey();
+            new_account = Some(Arc::new(to_keypair));
+            solana_sdk::stake::instruction::split(&account.address, &fee_payer, amount, &to_address)
+        }
+        Operation::Deactivate => vec![solana_sdk::stake::instruction::deactivate_stake(
+            &account.address,
+            &fee_payer,
+        )],
+        Operation::Withdraw => {
+            let to_address = recipient_pubkey(
+                &recipient,
+                "account address to deposit the withdrawn funds to",
+            )?;
+            if account.token.is_sol() {
+                let custodian = match state.custodian {
+                    Some(ref custodian) => Some(signer_pubkey(custodian)?),
+                    None => None,
+                };
+                vec![solana_sdk::stake::instruction::withdraw(
+                    &account.address,
+                    &fee_payer,
+                    &to_address,
+                    amount,
+                    custodian.as_ref(),
+                )]
+            } else {
+                vec![]
+            }
+        }
+        Operation::Delegate => {
+            let to_address = recipient_pubkey(&recipient, "validator address to delegate to")?;
+            vec![solana_sdk::stake::instruction::delegate_stake(
+                &account.address,
+                &fee_payer,
+                &to_address,
+            )]
+        }
+        Operation::Merge => {
+            let into_address = recipient_pubkey(&recipient, "account address to be merged into")?;
+            solana_sdk::stake::instruction::merge(&into_address, &account.address, &fee_payer)
+        }
+        Operation::NewStake => {
+            if amount == 0 {
+                return Err("Enter amount or select lots to fund the new stake account".to_string());
+            }
+            let vote_address = match recipient {
+                Some(_) => Some(recipient_pubkey(
+                    &recipient,
+                    "validator address to delegate to",
+                )?),
+                None => None,
+            };
+            let stake_address = match state.seed {
+                Some(ref seed) => stake_address_with_seed(&account.address, seed)?,
+                None => {
+                    let stake_keypair = Keypair::new();
+                    let stake_address = stake_keypair.pubkey();
+                    new_account = Some(Arc::new(stake_keypair));
+                    stake_address
+                }
+            };
+            recipient = Some(match state.seed {
+                Some(ref seed) => format!("{stake_address} (seed {seed})"),
+                None => format!("{stake_address} (new random keypair)"),
+            });
+            if let Some(vote_address) = vote_address {
+                recipient = recipient.map(|x| format!("{x}, delegated to {vote_address}"));
+            }
+            create_stake_instructions(
+                &account.address,
+                &stake_address,
+                state.seed.as_deref(),
+                vote_address.as_ref(),
+                amount,
+            )
+        }
+        Operation::Authorize => {
+            let new_authority = recipient_pubkey(&recipient, "new authority address")?;
+            let custodian = match state.custodian {
+                Some(ref custodian) => Some(signer_pubkey(custodian)?),
+                None => None,
+            };
+            recipient = Some(format!("{new_authority} as {}", state.authorization));
+            authorize_instructions(
+                &account.address,
+                &fee_payer,
+                &new_authority,
+                state.authorization,
+                custodian.as_ref(),
+            )
+        }
+        Operation::SetLockup => {
+            let new_custodian = match recipient {
+                Some(_) => Some(recipient_pubkey(&recipient, "new custodian address")?),
+                None => None,
+            };
+            let lockup = lockup_args(state, new_custodian)?;
+            let custodian = match state.custodian {
+                Some(ref custodian) => signer_pubkey(custodian)?,
+                None => fee_payer,
+            };
+            recipient = new_custodian.map(|x| format!("{x} as new custodian"));
+            vec![solana_sdk::stake::instruction::set_lockup(
+                &account.address,
+                &lockup,
+                &custodian,
+            )]
         }
-    }
+        Operation::MoveStake | Operation::MoveLamports => {
+            if amount == 0 {
+                return Err(format!("Enter amount or select lots to {operation}"));
+            }
+            let to_address = recipient_pubkey(&recipient, "stake account address to move to")?;
+            vec![move_instruction(
+                operation,
+                &account.address,
+                &to_address,
+                &fee_payer,
+                amount,
+            )]
+        }
+        Operation::Swap => vec![],
+    };
+    let (fee, simulation) = if instructions.is_empty() {
+        (
+            None,
+            "  Not available, the transaction is built when the operation runs\n".to_string(),
+        )
+    } else {
+        let rpc = RPC.read().unwrap();
+        match simulate_instructions(rpc.default(), &instructions, &fee_payer) {
+            Ok((fee, simulation)) => (Some(fee), simulation),
+            Err(e) => (None, format!("  Failed to simulate: {e}\n")),
+        }
+    };
+    Ok(Preview {
+        operation,
+        account,
+        state: state.clone(),
+        selected,
+        fee_payer,
+        recipient,
+        amount,
+        lots,
+        new_account,
+        instructions: instructions.iter().map(describe_instruction).collect(),
+        fee,
+        simulation,
+    })
 }
 
-async fn do_split(
-    selected_account: &mut Signal<Option<TrackedAccount>>,
-    state: &mut Signal<State>,
+async fn do_preview(
+    operation: Operation,
+    selected_account: &Signal<Option<TrackedAccount>>,
+    state: &Signal<State>,
 ) {
     let mut log = use_context::<GlobalState>().log;
-    let mut selected = use_context::<GlobalState>().selected;
+    let mut pending = use_context::<GlobalState>().pending;
+    let selected = use_context::<GlobalState>().selected;
     *log.write() = None;
-    if selected.read().is_empty() || selected_account.read().is_none() {
-        *log.write() = Some("Select account and lots to split".to_string());
+    match make_preview(
+        operation,
+        selected_account.read().clone(),
+        &state.read(),
+        &selected.read(),
+    ) {
+        // swaps and token transfers are built by sys when they run, so
+        // there is no transaction to simulate
+        Ok(preview) if state.read().dry_run && preview.instructions.is_empty() => {
+            *log.write() = Some(format!(
+                "Dry run is on and {operation} can't be simulated, nothing was signed or sent\n{}",
+                preview.report()
+            ))
+        }
+        Ok(preview) if state.read().dry_run => {
+            *log.write() = Some(format!(
+                "Dry run {operation}, nothing was signed or sent\n{}",
+                preview.report()
+            ))
+        }
+        Ok(preview) => *pending.write() = Some(preview),
+        Err(e) => *log.write() = Some(e),
+    }
+}
+
+/// Runs the previewed operation with the values it was previewed with.
+async fn do_operation(preview: Preview) {
+    let operation = preview.operation;
+    if preview.state.dry_run {
+        *(use_context::<GlobalState>().log.write()) =
+            Some(format!("Dry run is on, {operation} was not signed or sent"));
         return;
     }
-    if state.read().authority.is_none() {
-        *log.write() = Some("Enter staking authority keypair for account to be split".to_string());
+    if let Some(ref holder) = *use_context::<GlobalState>().read_only.read() {
+        *(use_context::<GlobalState>().log.write()) = Some(format!(
+            "The sys database is locked by {holder}, {operation} was not signed or sent"
+        ));
         return;
     }
+    match operation {
+        Operation::Split => {
+            do_split(&preview).await;
+            *(use_context::<GlobalState>().reload.write()) = true;
+        }
+        Operation::Deactivate => do_deactivate(&preview).await,
+        Operation::Withdraw => {
+            do_withdraw(&preview).await;
+            *(use_context::<GlobalState>().reload.write()) = true;
+            *(use_context::<GlobalState>().xupdate.write()) = true;
+        }
+        Operation::Delegate => do_delegate(&preview).await,
+        Operation::Swap => {
+            do_swap(&preview).await;
+            *(use_context::<GlobalState>().reload.write()) = true;
+        }
+        Operation::Merge => {
+            do_merge(&preview).await;
+            *(use_context::<GlobalState>().reload.write()) = true;
+        }
+        Operation::NewStake => {
+            do_new_stake(&preview).await;
+            *(use_context::<GlobalState>().reload.write()) = true;
+        }
+        Operation::Authorize => do_authorize(&preview).await,
+        Operation::SetLockup => do_set_lockup(&preview).await,
+        Operation::MoveStake | Operation::MoveLamports => {
+            do_move(&preview).await;
+            *(use_context::<GlobalState>().reload.write()) = true;
+        }
+    }
+}
+
+async fn do_split(preview: &Preview) {
+    let mut log = use_context::<GlobalState>().log;
+    let mut selected = use_context::<GlobalState>().selected;
+    *log.write() = None;
+    let (account, state) = (&preview.account, &preview.state);
     let rpc = RPC.read().unwrap();
     let mut db = DB.write().unwrap();
-    let account = selected_account.read().clone().unwrap();
     let from_address = account.address;
-    let amount = account
-        .lots
-        .iter()
-        .filter(|x| selected.read().contains(&x.lot_number))
-        .fold(0, |acc, x| acc + x.amount);
+    let amount = preview.amount;
     let description = None;
-    let lot_selection_method = LotSelectionMethod::default();
-    let lot_numbers = account
-        .lots
-        .iter()
-        .filter(|x| selected.read().contains(&x.lot_number))
-        .map(|x| x.lot_number)
-        .collect();
-    let authority = state.read().authority.clone().unwrap();
+    let lot_selection_method = state.lot_method.lot_selection_method();
+    let lot_numbers = preview.lot_numbers();
+    let authority = state.authority.clone().unwrap();
     let (authority_signer, authority_address) = make_signer!(authority, log);
-    let recipient = state.read().recipient.clone();
-    let to_keypair = recipient.map(|r| {
-        let arg_matches = make_arg_matches!("to", r, is_keypair);
-        keypair_of(&arg_matches, "to").unwrap()
-    });
+    let to_keypair = preview.new_account.as_ref().map(|x| x.insecure_clone());
     let if_balance_exceeds = None;
     let priority_fee = PriorityFee::default_auto();
     let mut buffer = std::io::BufWriter::new(Vec::new());
@@ -1454,7 +4058,7 @@ async fn do_split(
         Some(amount),
         description,
         lot_selection_method,
-        Some(lot_numbers),
+        lot_numbers,
         authority_address,
         vec![authority_signer],
         to_keypair,
@@ -1473,30 +4077,17 @@ async fn do_split(
         return;
     }
     adjust_balance(&mut db, authority_address);
-    *selected_account.write() = None;
+    *use_context::<GlobalState>().account.write() = None;
     selected.write().clear();
     let bytes = buffer.into_inner().unwrap();
     *log.write() = Some(String::from_utf8(bytes).unwrap());
 }
 
-async fn do_deactivate(
-    selected_account: &mut Signal<Option<TrackedAccount>>,
-    state: &Signal<State>,
-) {
+async fn do_deactivate(preview: &Preview) {
     let mut log = use_context::<GlobalState>().log;
-    let state = state.read();
+    let (account, state) = (&preview.account, &preview.state);
     *log.write() = None;
-    if selected_account.read().is_none() {
-        *log.write() = Some("Select account to deactivate".to_string());
-        return;
-    }
-    if state.authority.is_none() {
-        *log.write() =
-            Some("Enter staking authority keypair for account to be deactivated".to_string());
-        return;
-    }
     let rpc = RPC.read().unwrap();
-    let account = selected_account.read().clone().unwrap();
     let authority = state.authority.clone().unwrap();
     *log.write() = Some(format!(
         "deactivate-stake --stake-authority {} {}\nCheck ledger device for signing",
@@ -1520,52 +4111,23 @@ async fn do_deactivate(
     }
     let mut db = DB.write().unwrap();
     adjust_balance(&mut db, authority_address);
-    *selected_account.write() = None;
+    *use_context::<GlobalState>().account.write() = None;
     let bytes = buffer.into_inner().unwrap();
     *log.write() = Some(String::from_utf8(bytes).unwrap());
 }
 
-async fn do_withdraw(
-    selected_account: &mut Signal<Option<TrackedAccount>>,
-    state: &mut Signal<State>,
-) {
+async fn do_withdraw(preview: &Preview) {
     let mut log = use_context::<GlobalState>().log;
     let mut selected = use_context::<GlobalState>().selected;
     *log.write() = None;
-    if selected.read().is_empty() || selected_account.read().is_none() {
-        *log.write() = Some("Select account and lots to withdraw".to_string());
-        return;
-    }
-    if state.read().recipient.is_none() {
-        *log.write() = Some("Enter account address to deposit the withdrawn funds to".to_string());
-        return;
-    }
-    if state.read().authority.is_none() {
-        *log.write() =
-            Some("Enter withdraw authority keypair for account to withdraw from".to_string());
-        return;
-    }
+    let (account, state) = (&preview.account, &preview.state);
     let rpc = RPC.read().unwrap();
     let mut db = DB.write().unwrap();
-    let account = selected_account.read().clone().unwrap();
     let from_address = account.address;
-    let amount = if state.read().amount.unwrap_or_default() > 0. {
-        account.token.amount(state.read().amount.unwrap())
-    } else {
-        account
-            .lots
-            .iter()
-            .filter(|x| selected.read().contains(&x.lot_number))
-            .fold(0, |acc, x| acc + x.amount)
-    };
-    let lot_numbers = account
-        .lots
-        .iter()
-        .filter(|x| selected.read().contains(&x.lot_number))
-        .map(|x| x.lot_number)
-        .collect();
-    let lot_selection_method = LotSelectionMethod::default();
-    let recipient = state.read().recipient.clone().unwrap();
+    let amount = preview.amount;
+    let lot_numbers = preview.lot_numbers();
+    let lot_selection_method = state.lot_method.lot_selection_method();
+    let recipient = state.recipient.clone().unwrap();
     let arg_matches = make_arg_matches!("to", recipient, is_valid_pubkey);
     let to_address = match pubkey_of(&arg_matches, "to") {
         Some(v) => v,
@@ -1574,15 +4136,15 @@ async fn do_withdraw(
             return;
         }
     };
-    let authority = state.read().authority.clone().unwrap();
+    let authority = state.authority.clone().unwrap();
     let mut buffer = std::io::BufWriter::new(Vec::new());
     if !account.token.is_sol() {
         if let Err(e) = process_token_transfer(
-            &state.read().url.clone().unwrap(),
+            &state.url.clone().unwrap(),
             &authority,
             &account.token.mint().to_string(),
             &format!("{}", account.token.ui_amount(amount)),
-            &state.read().recipient.clone().unwrap(),
+            &recipient,
             &mut buffer,
         )
         .await
@@ -1597,14 +4159,14 @@ async fn do_withdraw(
         }
         let (_, authority_address) = make_signer!(authority, log);
         adjust_balance(&mut db, authority_address);
-        *selected_account.write() = None;
+        *use_context::<GlobalState>().account.write() = None;
         selected.write().clear();
         if let Err(e) = db.record_drop(
             account.address,
             account.token,
             amount,
-            LotSelectionMethod::LastInFirstOut,
-            Some(lot_numbers),
+            lot_selection_method,
+            lot_numbers,
         ) {
             *log.write() = Some(format!("Failed to drop lots: {e:#?}"));
         }
@@ -1612,19 +4174,28 @@ async fn do_withdraw(
         *log.write() = Some(String::from_utf8(bytes).unwrap());
         return;
     }
-    let custodian = None;
     let (authority_signer, authority_address) = make_signer!(authority, log);
+    let mut signers = vec![authority_signer];
+    let custodian = match state.custodian.clone() {
+        Some(custodian) if custodian != authority => {
+            let (custodian_signer, custodian_address) = make_signer!(custodian, log);
+            signers.push(custodian_signer);
+            Some(custodian_address)
+        }
+        Some(_) => Some(authority_address),
+        None => None,
+    };
     if let Err(e) = process_stake_withdraw(
         &mut db,
         &rpc,
         from_address,
         authority_address,
         to_address,
-        custodian,
+        custodian.as_ref(),
         Some(amount),
         lot_selection_method,
-        Some(lot_numbers),
-        vec![authority_signer],
+        lot_numbers,
+        signers,
         &mut buffer,
     )
     .await
@@ -1638,30 +4209,17 @@ async fn do_withdraw(
         return;
     }
     adjust_balance(&mut db, authority_address);
-    *selected_account.write() = None;
+    *use_context::<GlobalState>().account.write() = None;
     selected.write().clear();
     let bytes = buffer.into_inner().unwrap();
     *log.write() = Some(String::from_utf8(bytes).unwrap());
 }
 
-async fn do_delegate(selected_account: &mut Signal<Option<TrackedAccount>>, state: &Signal<State>) {
+async fn do_delegate(preview: &Preview) {
     let mut log = use_context::<GlobalState>().log;
-    let state = state.read();
+    let (account, state) = (&preview.account, &preview.state);
     *log.write() = None;
-    if selected_account.read().is_none() {
-        *log.write() = Some("Select account to delegate".to_string());
-        return;
-    }
-    if state.recipient.is_none() {
-        *log.write() = Some("Enter validator address to delegate to".to_string());
-        return;
-    }
-    if state.authority.is_none() {
-        *log.write() = Some("Enter staking authority keypair for account to delegate".to_string());
-        return;
-    }
     let rpc = RPC.read().unwrap();
-    let account = selected_account.read().clone().unwrap();
     let from_address = account.address;
     let recipient = state.recipient.clone().unwrap();
     let arg_matches = make_arg_matches!("to", recipient, is_valid_pubkey);
@@ -1692,30 +4250,17 @@ async fn do_delegate(selected_account: &mut Signal<Option<TrackedAccount>>, stat
     }
     let mut db = DB.write().unwrap();
     adjust_balance(&mut db, authority_address);
-    *selected_account.write() = None;
+    *use_context::<GlobalState>().account.write() = None;
     let bytes = buffer.into_inner().unwrap();
     *log.write() = Some(String::from_utf8(bytes).unwrap());
 }
 
-async fn do_swap(selected_account: &mut Signal<Option<TrackedAccount>>, state: &mut Signal<State>) {
+async fn do_swap(preview: &Preview) {
     let mut selected = use_context::<GlobalState>().selected;
-    let state = state.read();
+    let (account, state) = (&preview.account, &preview.state);
     consume_context::<GlobalState>().log.set(None);
-    if selected.read().is_empty() || selected_account.read().is_none() {
-        consume_context::<GlobalState>()
-            .log
-            .set(Some("Select account and lots to swap".to_string()));
-        return;
-    }
-    if state.authority.is_none() {
-        consume_context::<GlobalState>()
-            .log
-            .set(Some("Enter signer keypair for swap".to_string()));
-        return;
-    }
     let rpc = RPC.read().unwrap();
     let mut db = DB.write().unwrap();
-    let account = selected_account.read().clone().unwrap();
     let authority = state.authority.clone().unwrap();
     let mut log = use_context::<GlobalState>().log;
     let (signer, address) = make_signer!(authority, log);
@@ -1728,20 +4273,11 @@ async fn do_swap(selected_account: &mut Signal<Option<TrackedAccount>>, state: &
             Token::USDC
         },
     );
-    let amount = account
-        .lots
-        .iter()
-        .filter(|x| selected.read().contains(&x.lot_number))
-        .fold(0, |acc, x| acc + x.amount);
+    let amount = preview.amount;
     let ui_amount = Some(from_token.ui_amount(amount));
     let slippage_bps = 100u64;
-    let lot_selection_method = LotSelectionMethod::LastInFirstOut;
-    let lot_numbers = account
-        .lots
-        .iter()
-        .filter(|x| selected.read().contains(&x.lot_number))
-        .map(|x| x.lot_number)
-        .collect();
+    let lot_selection_method = state.lot_method.lot_selection_method();
+    let lot_numbers = preview.lot_numbers();
     let signature = None;
     let if_from_balance_exceeds = None;
     let for_no_less_than = None;
@@ -1762,7 +4298,7 @@ async fn do_swap(selected_account: &mut Signal<Option<TrackedAccount>>, state: &
         ui_amount,
         slippage_bps,
         lot_selection_method,
-        Some(lot_numbers),
+        lot_numbers,
         vec![signer],
         signature,
         if_from_balance_exceeds,
@@ -1777,7 +4313,7 @@ async fn do_swap(selected_account: &mut Signal<Option<TrackedAccount>>, state: &
         Ok(()) => match process_sync_swaps(&mut db, rpc.default(), &notifier, &mut buffer).await {
             Ok(()) => {
                 adjust_balance(&mut db, address);
-                *selected_account.write() = None;
+                *use_context::<GlobalState>().account.write() = None;
                 selected.write().clear();
                 let bytes = buffer.into_inner().unwrap();
                 consume_context::<GlobalState>()
@@ -1799,61 +4335,312 @@ async fn do_swap(selected_account: &mut Signal<Option<TrackedAccount>>, state: &
     }
 }
 
-async fn do_merge(selected_account: &mut Signal<Option<TrackedAccount>>, state: &Signal<State>) {
+async fn do_merge(preview: &Preview) {
+    let mut log = use_context::<GlobalState>().log;
+    let (account, state) = (&preview.account, &preview.state);
+    *log.write() = None;
+    let rpc = RPC.read().unwrap();
+    let mut db = DB.write().unwrap();
+    let from_address = account.address;
+    let recipient = state.recipient.clone().unwrap();
+    let arg_matches = make_arg_matches!("to", recipient, is_valid_pubkey);
+    let into_address = match pubkey_of(&arg_matches, "to") {
+        Some(v) => v,
+        None => {
+            *log.write() = Some(format!("Invalid address to merge into {}", recipient));
+            return;
+        }
+    };
+    let authority = state.authority.clone().unwrap();
+    let (authority_signer, authority_address) = make_signer!(authority, log);
+    let priority_fee = PriorityFee::default_auto();
+    let signature = None;
+    let mut buffer = std::io::BufWriter::new(Vec::new());
+    if let Err(e) = process_account_merge(
+        &mut db,
+        &rpc,
+        from_address,
+        into_address,
+        authority_address,
+        vec![authority_signer],
+        priority_fee,
+        signature,
+        &mut buffer,
+    )
+    .await
+    {
+        *log.write() = Some(format!(
+            "Failed sys account merge {:?} --into {:?}: {:?}",
+            from_address, into_address, e,
+        ));
+        return;
+    }
+    adjust_balance(&mut db, authority_address);
+    *use_context::<GlobalState>().account.write() = None;
+    let bytes = buffer.into_inner().unwrap();
+    *log.write() = Some(String::from_utf8(bytes).unwrap());
+}
+
+async fn do_new_stake(preview: &Preview) {
+    let mut log = use_context::<GlobalState>().log;
+    let mut selected = use_context::<GlobalState>().selected;
+    let (account, state) = (&preview.account, &preview.state);
+    *log.write() = None;
+    let rpc = RPC.read().unwrap();
+    let mut db = DB.write().unwrap();
+    if !account.token.is_sol() {
+        *log.write() = Some(format!(
+            "Stake accounts can only be funded from SOL accounts, not {}",
+            account.token
+        ));
+        return;
+    }
+    let from_address = account.address;
+    let amount = preview.amount;
+    let lot_numbers = preview.lot_numbers();
+    let lot_selection_method = state.lot_method.lot_selection_method();
+    let vote_address = match state.recipient {
+        Some(ref recipient) => {
+            let arg_matches = make_arg_matches!("to", recipient, is_valid_pubkey);
+            match pubkey_of(&arg_matches, "to") {
+                Some(v) => Some(v),
+                None => {
+                    *log.write() = Some(format!("Invalid validator address {}", recipient));
+                    return;
+                }
+            }
+        }
+        None => None,
+    };
+    let authority = state.authority.clone().unwrap();
+    let (authority_signer, authority_address) = make_signer!(authority, log);
+    if authority_address != from_address {
+        *log.write() = Some(format!(
+            "Enter keypair of {from_address} to fund the new stake account, not {authority_address}"
+        ));
+        return;
+    }
+    let mut signers = vec![authority_signer];
+    let stake_address = match state.seed {
+        Some(ref seed) => match stake_address_with_seed(&from_address, seed) {
+            Ok(v) => v,
+            Err(e) => {
+                *log.write() = Some(e);
+                return;
+            }
+        },
+        None => {
+            let stake_keypair = preview
+                .new_account
+                .as_ref()
+                .expect("new stake account keypair")
+                .insecure_clone();
+            let stake_address = stake_keypair.pubkey();
+            signers.push(Box::new(stake_keypair));
+            stake_address
+        }
+    };
+    let mut buffer = std::io::BufWriter::new(Vec::new());
+    if let Err(e) = process_stake_create(
+        &mut db,
+        &rpc,
+        from_address,
+        stake_address,
+        state.seed.clone(),
+        vote_address,
+        amount,
+        lot_selection_method,
+        lot_numbers,
+        signers,
+        &mut buffer,
+    )
+    .await
+    {
+        *log.write() = Some(format!(
+            "Failed to create stake account {:?} from {:?} {}: {:?}",
+            stake_address,
+            from_address,
+            account.token.format_amount(amount),
+            e,
+        ));
+        return;
+    }
+    adjust_balance(&mut db, authority_address);
+    *use_context::<GlobalState>().account.write() = None;
+    selected.write().clear();
+    let bytes = buffer.into_inner().unwrap();
+    *log.write() = Some(String::from_utf8(bytes).unwrap());
+}
+
+async fn do_authorize(preview: &Preview) {
+    let mut log = use_context::<GlobalState>().log;
+    let (account, state) = (&preview.account, &preview.state);
+    *log.write() = None;
+    let rpc = RPC.read().unwrap();
+    let stake_address = account.address;
+    let recipient = state.recipient.clone().unwrap();
+    let arg_matches = make_arg_matches!("to", recipient, is_valid_pubkey);
+    let new_authority = match pubkey_of(&arg_matches, "to") {
+        Some(v) => v,
+        None => {
+            *log.write() = Some(format!("Invalid new authority address {}", recipient));
+            return;
+        }
+    };
+    let authority = state.authority.clone().unwrap();
+    *log.write() = Some(format!(
+        "stake-authorize {} --new-{} {}\nCheck ledger device for signing",
+        stake_address, state.authorization, new_authority,
+    ));
+    let (authority_signer, authority_address) = make_signer!(authority, log);
+    let mut signers = vec![authority_signer];
+    let custodian_address = match state.custodian.clone() {
+        Some(custodian) if custodian != authority => {
+            let (custodian_signer, custodian_address) = make_signer!(custodian, log);
+            signers.push(custodian_signer);
+            Some(custodian_address)
+        }
+        Some(_) => Some(authority_address),
+        None => None,
+    };
+    let mut buffer = std::io::BufWriter::new(Vec::new());
+    if let Err(e) = process_stake_authorize(
+        &rpc,
+        stake_address,
+        authority_address,
+        new_authority,
+        state.authorization,
+        custodian_address,
+        signers,
+        &mut buffer,
+    )
+    .await
+    {
+        *log.write() = Some(format!(
+            "Failed solana stake-authorize {} --new-{} {}: {:?}",
+            stake_address, state.authorization, new_authority, e,
+        ));
+        return;
+    }
+    let mut db = DB.write().unwrap();
+    adjust_balance(&mut db, authority_address);
+    *use_context::<GlobalState>().account.write() = None;
+    let bytes = buffer.into_inner().unwrap();
+    *log.write() = Some(String::from_utf8(bytes).unwrap());
+}
+
+async fn do_set_lockup(preview: &Preview) {
+    let mut log = use_context::<GlobalState>().log;
+    let (account, state) = (&preview.account, &preview.state);
+    *log.write() = None;
+    let new_custodian = match state.recipient {
+        Some(ref recipient) => {
+            let arg_matches = make_arg_matches!("to", recipient, is_valid_pubkey);
+            match pubkey_of(&arg_matches, "to") {
+                Some(v) => Some(v),
+                None => {
+                    *log.write() = Some(format!("Invalid new custodian address {}", recipient));
+                    return;
+                }
+            }
+        }
+        None => None,
+    };
+    let lockup = match lockup_args(state, new_custodian) {
+        Ok(v) => v,
+        Err(e) => {
+            *log.write() = Some(e);
+            return;
+        }
+    };
+    let rpc = RPC.read().unwrap();
+    let stake_address = account.address;
+    let authority = state.authority.clone().unwrap();
+    let (authority_signer, authority_address) = make_signer!(authority, log);
+    let mut signers = vec![authority_signer];
+    let custodian_address = match state.custodian.clone() {
+        Some(custodian) if custodian != authority => {
+            let (custodian_signer, custodian_address) = make_signer!(custodian, log);
+            signers.push(custodian_signer);
+            custodian_address
+        }
+        _ => authority_address,
+    };
+    let mut buffer = std::io::BufWriter::new(Vec::new());
+    if let Err(e) = process_stake_set_lockup(
+        &rpc,
+        stake_address,
+        authority_address,
+        custodian_address,
+        lockup,
+        signers,
+        &mut buffer,
+    )
+    .await
+    {
+        *log.write() = Some(format!(
+            "Failed solana stake-set-lockup {} --custodian {}: {:?}",
+            stake_address, custodian_address, e,
+        ));
+        return;
+    }
+    let mut db = DB.write().unwrap();
+    adjust_balance(&mut db, authority_address);
+    *use_context::<GlobalState>().account.write() = None;
+    let bytes = buffer.into_inner().unwrap();
+    *log.write() = Some(String::from_utf8(bytes).unwrap());
+}
+
+async fn do_move(preview: &Preview) {
     let mut log = use_context::<GlobalState>().log;
-    let state = state.read();
+    let mut selected = use_context::<GlobalState>().selected;
+    let (operation, account, state) = (preview.operation, &preview.account, &preview.state);
     *log.write() = None;
-    if selected_account.read().is_none() {
-        *log.write() = Some("Select account to merge".to_string());
-        return;
-    }
-    if state.recipient.is_none() {
-        *log.write() = Some("Enter account address to be merged into".to_string());
-        return;
-    }
-    if state.authority.is_none() {
-        *log.write() = Some("Enter staking authority keypair for account to be merged".to_string());
-        return;
-    }
     let rpc = RPC.read().unwrap();
     let mut db = DB.write().unwrap();
-    let account = selected_account.read().clone().unwrap();
     let from_address = account.address;
+    let amount = preview.amount;
+    let lot_numbers = preview.lot_numbers();
+    let lot_selection_method = state.lot_method.lot_selection_method();
     let recipient = state.recipient.clone().unwrap();
     let arg_matches = make_arg_matches!("to", recipient, is_valid_pubkey);
-    let into_address = match pubkey_of(&arg_matches, "to") {
+    let to_address = match pubkey_of(&arg_matches, "to") {
         Some(v) => v,
         None => {
-            *log.write() = Some(format!("Invalid address to merge into {}", recipient));
+            *log.write() = Some(format!("Invalid stake account address {}", recipient));
             return;
         }
     };
     let authority = state.authority.clone().unwrap();
     let (authority_signer, authority_address) = make_signer!(authority, log);
-    let priority_fee = PriorityFee::default_auto();
-    let signature = None;
     let mut buffer = std::io::BufWriter::new(Vec::new());
-    if let Err(e) = process_account_merge(
+    if let Err(e) = process_stake_move(
         &mut db,
         &rpc,
+        operation,
         from_address,
-        into_address,
+        to_address,
         authority_address,
+        amount,
+        lot_selection_method,
+        lot_numbers,
         vec![authority_signer],
-        priority_fee,
-        signature,
         &mut buffer,
     )
     .await
     {
         *log.write() = Some(format!(
-            "Failed sys account merge {:?} --into {:?}: {:?}",
-            from_address, into_address, e,
+            "Failed to {operation} {} from {:?} to {:?}: {:?}",
+            account.token.format_amount(amount),
+            from_address,
+            to_address,
+            e,
         ));
         return;
     }
     adjust_balance(&mut db, authority_address);
-    *selected_account.write() = None;
+    *use_context::<GlobalState>().account.write() = None;
+    selected.write().clear();
     let bytes = buffer.into_inner().unwrap();
     *log.write() = Some(String::from_utf8(bytes).unwrap());
 }
@@ -1861,12 +4648,16 @@ async fn do_merge(selected_account: &mut Signal<Option<TrackedAccount>>, state:
 async fn do_disburse(
     xaccount: Option<(Exchange, String)>,
     xpmethod: Option<(Exchange, String)>,
-    xclients: &Signal<Option<HashMap<Exchange, Box<dyn ExchangeClient>>>>,
+    xclients: &Signal<Option<Arc<ExchangeClients>>>,
     state: &Signal<State>,
 ) {
     let mut log = use_context::<GlobalState>().log;
     let state = state.read();
     let xclients = xclients.read();
+    if state.dry_run {
+        *log.write() = Some("Dry run is on, no cash was disbursed".to_string());
+        return;
+    }
     if xaccount.is_none() {
         *log.write() = Some("Select exchange account from which to disburse cash".to_string());
         return;
@@ -1892,46 +4683,442 @@ async fn do_disburse(
             .map(|x| x.value.clone())
             .unwrap()
     };
-    let methods = client.payment_methods().await;
-    if let Err(e) = methods {
-        *log.write() = Some(format!("Couldn't get exchange payment methods {e}"));
-        return;
+    let methods = client.payment_methods().await;
+    if let Err(e) = methods {
+        *log.write() = Some(format!("Couldn't get exchange payment methods {e}"));
+        return;
+    }
+    let method = xpmethod.unwrap().1;
+    let currency = methods
+        .unwrap()
+        .iter()
+        .find(|x| x.id == method.clone())
+        .map(|x| x.currency.clone())
+        .unwrap();
+    let disbursement = client
+        .disburse_cash(account, amount, currency, method)
+        .await;
+    match disbursement {
+        Ok(d) => {
+            *log.write() = Some(format!(
+                "Disbursed cash ${}, fee ${}, reference {} {:#?}",
+                d.total, d.total_fee, d.user_reference, d.user_warnings,
+            ))
+        }
+        Err(e) => *log.write() = Some(format!("{e}")),
+    }
+}
+
+pub async fn process_stake_deactivate<T: Signers, W: Write>(
+    rpc_clients: &RpcClients,
+    stake_account: Pubkey,
+    stake_authority: Pubkey,
+    signers: T,
+    writer: &mut W,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let rpc_client = rpc_clients.default();
+    let (recent_blockhash, last_valid_block_height) =
+        rpc_client.get_latest_blockhash_with_commitment(rpc_client.commitment())?;
+    let instructions = vec![solana_sdk::stake::instruction::deactivate_stake(
+        &stake_account,
+        &stake_authority,
+    )];
+    let message = Message::new(&instructions, Some(&stake_authority));
+    let mut transaction = Transaction::new_unsigned(message);
+    transaction.message.recent_blockhash = recent_blockhash;
+    let simulation_result = rpc_client.simulate_transaction(&transaction)?.value;
+    if simulation_result.err.is_some() {
+        return Err(format!("Simulation failure: {simulation_result:?}").into());
+    }
+    transaction.try_sign(&signers, recent_blockhash)?;
+    let signature = transaction.signatures[0];
+    writeln!(writer, "Transaction signature: {signature}")?;
+
+    if !sys::send_transaction_until_expired(rpc_clients, &transaction, last_valid_block_height)
+        .unwrap_or_default()
+    {
+        return Err("Deactivate failed".into());
+    }
+
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn process_stake_withdraw<T: Signers, W: Write>(
+    db: &mut Db,
+    rpc_clients: &RpcClients,
+    stake_address: Pubkey,
+    stake_authority: Pubkey,
+    to_address: Pubkey,
+    custodian: Option<&Pubkey>,
+    amount: Option<u64>,
+    lot_selection_method: LotSelectionMethod,
+    lot_numbers: Option<HashSet<usize>>,
+    signers: T,
+    writer: &mut W,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let rpc_client = rpc_clients.default();
+    let (recent_blockhash, last_valid_block_height) =
+        rpc_client.get_latest_blockhash_with_commitment(rpc_client.commitment())?;
+    let token = MaybeToken::SOL();
+    let stake_account = db
+        .get_account(stake_address, MaybeToken::SOL())
+        .ok_or_else(|| format!("SOL account does not exist for {stake_address}"))?;
+    if db.get_account(to_address, MaybeToken::SOL()).is_none() {
+        return Err(format!("Account {} ({}) does not exist", to_address, token,).into());
+    }
+    let (withdraw_all, amount) = match amount {
+        None => (true, stake_account.last_update_balance),
+        Some(amount) => (false, amount),
+    };
+    let (meta, clock) = get_stake_meta(rpc_client, &stake_address)?;
+    if meta.lockup.is_in_force(&clock, custodian) {
+        return Err(format!(
+            "Lockup is in force {}, enter the custodian keypair to withdraw",
+            format_lockup(&meta.lockup)
+        )
+        .into());
+    }
+    let instructions = vec![solana_sdk::stake::instruction::withdraw(
+        &stake_address,
+        &stake_authority,
+        &to_address,
+        amount,
+        custodian,
+    )];
+    let message = Message::new(&instructions, Some(&stake_authority));
+    let mut transaction = Transaction::new_unsigned(message);
+    transaction.message.recent_blockhash = recent_blockhash;
+    let simulation_result = rpc_client.simulate_transaction(&transaction)?.value;
+    if simulation_result.err.is_some() {
+        return Err(format!("Simulation failure: {simulation_result:?}").into());
+    }
+    transaction.try_sign(&signers, recent_blockhash)?;
+    let signature = transaction.signatures[0];
+    writeln!(writer, "Transaction signature: {signature}")?;
+    db.record_transfer(
+        signature,
+        last_valid_block_height,
+        Some(amount),
+        stake_address,
+        token,
+        to_address,
+        token,
+        lot_selection_method,
+        lot_numbers,
+    )?;
+    if !sys::send_transaction_until_expired(rpc_clients, &transaction, last_valid_block_height)
+        .unwrap_or_default()
+    {
+        db.cancel_transfer(signature)?;
+        return Err("Withdraw failed".into());
+    }
+    writeln!(writer, "Withdraw confirmed: {signature}")?;
+    let when = sys::rpc_client_utils::get_signature_date(rpc_client, signature).await?;
+    db.confirm_transfer(signature, when)?;
+    if withdraw_all {
+        let stake_account = db.get_account(stake_address, MaybeToken::SOL()).unwrap();
+        assert!(stake_account.lots.is_empty());
+        db.remove_account(stake_address, MaybeToken::SOL())?;
+    }
+    Ok(())
+}
+
+pub async fn process_stake_delegate<T: Signers, W: Write>(
+    rpc_clients: &RpcClients,
+    stake_address: Pubkey,
+    stake_authority: Pubkey,
+    to_address: Pubkey,
+    signers: T,
+    writer: &mut W,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let rpc_client = rpc_clients.default();
+    let (recent_blockhash, last_valid_block_height) =
+        rpc_client.get_latest_blockhash_with_commitment(rpc_client.commitment())?;
+    let instructions = vec![solana_sdk::stake::instruction::delegate_stake(
+        &stake_address,
+        &stake_authority,
+        &to_address,
+    )];
+    let message = Message::new(&instructions, Some(&stake_authority));
+    let mut transaction = Transaction::new_unsigned(message);
+    transaction.message.recent_blockhash = recent_blockhash;
+    let simulation_result = rpc_client.simulate_transaction(&transaction)?.value;
+    if simulation_result.err.is_some() {
+        return Err(format!("Simulation failure: {simulation_result:?}").into());
+    }
+    transaction.try_sign(&signers, recent_blockhash)?;
+    let signature = transaction.signatures[0];
+    writeln!(writer, "Transaction signature: {signature}")?;
+    if !sys::send_transaction_until_expired(rpc_clients, &transaction, last_valid_block_height)
+        .unwrap_or_default()
+    {
+        return Err("Delegate failed".into());
+    }
+    Ok(())
+}
+
+fn stake_address_with_seed(base: &Pubkey, seed: &str) -> Result<Pubkey, String> {
+    Pubkey::create_with_seed(base, seed, &solana_sdk::stake::program::id())
+        .map_err(|e| format!("Invalid seed {seed}: {e:?}"))
+}
+
+fn create_stake_instructions(
+    from_address: &Pubkey,
+    stake_address: &Pubkey,
+    seed: Option<&str>,
+    vote_address: Option<&Pubkey>,
+    lamports: u64,
+) -> Vec<Instruction> {
+    let authorized = solana_sdk::stake::state::Authorized::auto(from_address);
+    let lockup = solana_sdk::stake::state::Lockup::default();
+    match (seed, vote_address) {
+        (Some(seed), Some(vote_address)) => {
+            solana_sdk::stake::instruction::create_account_with_seed_and_delegate_stake(
+                from_address,
+                stake_address,
+                from_address,
+                seed,
+                vote_address,
+                &authorized,
+                &lockup,
+                lamports,
+            )
+        }
+        (Some(seed), None) => solana_sdk::stake::instruction::create_account_with_seed(
+            from_address,
+            stake_address,
+            from_address,
+            seed,
+            &authorized,
+            &lockup,
+            lamports,
+        ),
+        (None, Some(vote_address)) => {
+            solana_sdk::stake::instruction::create_account_and_delegate_stake(
+                from_address,
+                stake_address,
+                vote_address,
+                &authorized,
+                &lockup,
+                lamports,
+            )
+        }
+        (None, None) => solana_sdk::stake::instruction::create_account(
+            from_address,
+            stake_address,
+            &authorized,
+            &lockup,
+            lamports,
+        ),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn process_stake_create<T: Signers, W: Write>(
+    db: &mut Db,
+    rpc_clients: &RpcClients,
+    from_address: Pubkey,
+    stake_address: Pubkey,
+    seed: Option<String>,
+    vote_address: Option<Pubkey>,
+    amount: u64,
+    lot_selection_method: LotSelectionMethod,
+    lot_numbers: Option<HashSet<usize>>,
+    signers: T,
+    writer: &mut W,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let rpc_client = rpc_clients.default();
+    let (recent_blockhash, last_valid_block_height) =
+        rpc_client.get_latest_blockhash_with_commitment(rpc_client.commitment())?;
+    let token = MaybeToken::SOL();
+    if db.get_account(from_address, token).is_none() {
+        return Err(format!("Account {} ({}) does not exist", from_address, token).into());
+    }
+    if db.get_account(stake_address, token).is_some() {
+        return Err(format!("Account {} ({}) already exists", stake_address, token).into());
+    }
+    let instructions = create_stake_instructions(
+        &from_address,
+        &stake_address,
+        seed.as_deref(),
+        vote_address.as_ref(),
+        amount,
+    );
+    let message = Message::new(&instructions, Some(&from_address));
+    let mut transaction = Transaction::new_unsigned(message);
+    transaction.message.recent_blockhash = recent_blockhash;
+    let simulation_result = rpc_client.simulate_transaction(&transaction)?.value;
+    if simulation_result.err.is_some() {
+        return Err(format!("Simulation failure: {simulation_result:?}").into());
+    }
+    transaction.try_sign(&signers, recent_blockhash)?;
+    let signature = transaction.signatures[0];
+    writeln!(writer, "Transaction signature: {signature}")?;
+    let epoch = rpc_client.get_epoch_info()?.epoch;
+    db.add_account(TrackedAccount {
+        address: stake_address,
+        token,
+        description: match seed {
+            Some(ref seed) => format!("Stake account with seed {seed}"),
+            None => "Stake account".to_string(),
+        },
+        last_update_epoch: epoch,
+        last_update_balance: 0,
+        lots: vec![],
+        no_sync: None,
+    })?;
+    db.record_transfer(
+        signature,
+        last_valid_block_height,
+        Some(amount),
+        from_address,
+        token,
+        stake_address,
+        token,
+        lot_selection_method,
+        lot_numbers,
+    )?;
+    if !sys::send_transaction_until_expired(rpc_clients, &transaction, last_valid_block_height)
+        .unwrap_or_default()
+    {
+        db.cancel_transfer(signature)?;
+        db.remove_account(stake_address, token)?;
+        return Err("Create stake account failed".into());
+    }
+    writeln!(writer, "Created stake account {stake_address}")?;
+    let when = sys::rpc_client_utils::get_signature_date(rpc_client, signature).await?;
+    db.confirm_transfer(signature, when)?;
+    Ok(())
+}
+
+fn get_stake_meta(
+    rpc_client: &RpcClient,
+    stake_address: &Pubkey,
+) -> Result<(solana_sdk::stake::state::Meta, solana_sdk::clock::Clock), Box<dyn std::error::Error>>
+{
+    let account = rpc_client.get_account(stake_address)?;
+    if account.owner != solana_sdk::stake::program::id() {
+        return Err(format!("{stake_address} is not a stake account").into());
+    }
+    let stake_state: solana_sdk::stake::state::StakeStateV2 = account.state()?;
+    let meta = match stake_state {
+        solana_sdk::stake::state::StakeStateV2::Initialized(meta)
+        | solana_sdk::stake::state::StakeStateV2::Stake(meta, _, _) => meta,
+        _ => return Err(format!("Stake account {stake_address} is not initialized").into()),
+    };
+    let clock_account = rpc_client.get_account(&solana_sdk::sysvar::clock::id())?;
+    let clock: solana_sdk::clock::Clock =
+        solana_sdk::account::from_account(&clock_account).unwrap();
+    Ok((meta, clock))
+}
+
+fn format_timestamp(timestamp: i64) -> String {
+    DateTime::from_timestamp(timestamp, 0)
+        .map(|x| x.with_timezone(&Local).format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_else(|| timestamp.to_string())
+}
+
+fn format_lockup(lockup: &solana_sdk::stake::state::Lockup) -> String {
+    let until = format_timestamp(lockup.unix_timestamp);
+    format!(
+        "until {} or epoch {}, custodian {}",
+        until, lockup.epoch, lockup.custodian
+    )
+}
+
+fn lockup_args(
+    state: &State,
+    new_custodian: Option<Pubkey>,
+) -> Result<solana_sdk::stake::instruction::LockupArgs, String> {
+    let unix_timestamp = match state.lockup_date {
+        Some(ref date) => Some(
+            NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                .map_err(|e| format!("Invalid lockup date {date}: {e}"))?
+                .and_hms_opt(0, 0, 0)
+                .unwrap()
+                .and_utc()
+                .timestamp(),
+        ),
+        None => None,
+    };
+    let epoch = match state.lockup_epoch {
+        Some(ref epoch) => Some(
+            epoch
+                .parse::<u64>()
+                .map_err(|e| format!("Invalid lockup epoch {epoch}: {e}"))?,
+        ),
+        None => None,
+    };
+    if unix_timestamp.is_none() && epoch.is_none() && new_custodian.is_none() {
+        return Err("Enter lockup date, lockup epoch or new custodian address".to_string());
     }
-    let method = xpmethod.unwrap().1;
-    let currency = methods
-        .unwrap()
-        .iter()
-        .find(|x| x.id == method.clone())
-        .map(|x| x.currency.clone())
-        .unwrap();
-    let disbursement = client
-        .disburse_cash(account, amount, currency, method)
-        .await;
-    match disbursement {
-        Ok(d) => {
-            *log.write() = Some(format!(
-                "Disbursed cash ${}, fee ${}, reference {} {:#?}",
-                d.total, d.total_fee, d.user_reference, d.user_warnings,
-            ))
-        }
-        Err(e) => *log.write() = Some(format!("{e}")),
+    Ok(solana_sdk::stake::instruction::LockupArgs {
+        unix_timestamp,
+        epoch,
+        custodian: new_custodian,
+    })
+}
+
+fn authorize_instructions(
+    stake_address: &Pubkey,
+    stake_authority: &Pubkey,
+    new_authority: &Pubkey,
+    authorization: Authorization,
+    custodian: Option<&Pubkey>,
+) -> Vec<Instruction> {
+    let mut instructions = vec![];
+    if authorization != Authorization::Withdrawer {
+        instructions.push(solana_sdk::stake::instruction::authorize(
+            stake_address,
+            stake_authority,
+            new_authority,
+            solana_sdk::stake::state::StakeAuthorize::Staker,
+            None,
+        ));
+    }
+    if authorization != Authorization::Staker {
+        instructions.push(solana_sdk::stake::instruction::authorize(
+            stake_address,
+            stake_authority,
+            new_authority,
+            solana_sdk::stake::state::StakeAuthorize::Withdrawer,
+            custodian,
+        ));
     }
+    instructions
 }
 
-pub async fn process_stake_deactivate<T: Signers, W: Write>(
+#[allow(clippy::too_many_arguments)]
+pub async fn process_stake_authorize<T: Signers, W: Write>(
     rpc_clients: &RpcClients,
-    stake_account: Pubkey,
+    stake_address: Pubkey,
     stake_authority: Pubkey,
+    new_authority: Pubkey,
+    authorization: Authorization,
+    custodian: Option<Pubkey>,
     signers: T,
     writer: &mut W,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let rpc_client = rpc_clients.default();
+    let (meta, clock) = get_stake_meta(rpc_client, &stake_address)?;
+    if authorization != Authorization::Staker && meta.lockup.is_in_force(&clock, custodian.as_ref())
+    {
+        return Err(format!(
+            "Lockup is in force {}, the custodian has to sign",
+            format_lockup(&meta.lockup)
+        )
+        .into());
+    }
     let (recent_blockhash, last_valid_block_height) =
         rpc_client.get_latest_blockhash_with_commitment(rpc_client.commitment())?;
-    let instructions = vec![solana_sdk::stake::instruction::deactivate_stake(
-        &stake_account,
+    let instructions = authorize_instructions(
+        &stake_address,
         &stake_authority,
-    )];
+        &new_authority,
+        authorization,
+        custodian.as_ref(),
+    );
     let message = Message::new(&instructions, Some(&stake_authority));
     let mut transaction = Transaction::new_unsigned(message);
     transaction.message.recent_blockhash = recent_blockhash;
@@ -1942,52 +5129,36 @@ pub async fn process_stake_deactivate<T: Signers, W: Write>(
     transaction.try_sign(&signers, recent_blockhash)?;
     let signature = transaction.signatures[0];
     writeln!(writer, "Transaction signature: {signature}")?;
-
     if !sys::send_transaction_until_expired(rpc_clients, &transaction, last_valid_block_height)
         .unwrap_or_default()
     {
-        return Err("Deactivate failed".into());
+        return Err("Authorize failed".into());
     }
-
+    writeln!(
+        writer,
+        "New {authorization} authority of {stake_address}: {new_authority}"
+    )?;
     Ok(())
 }
 
-#[allow(clippy::too_many_arguments)]
-pub async fn process_stake_withdraw<T: Signers, W: Write>(
-    db: &mut Db,
+pub async fn process_stake_set_lockup<T: Signers, W: Write>(
     rpc_clients: &RpcClients,
     stake_address: Pubkey,
-    stake_authority: Pubkey,
-    to_address: Pubkey,
-    custodian: Option<&Pubkey>,
-    amount: Option<u64>,
-    lot_selection_method: LotSelectionMethod,
-    lot_numbers: Option<HashSet<usize>>,
+    fee_payer: Pubkey,
+    custodian: Pubkey,
+    lockup: solana_sdk::stake::instruction::LockupArgs,
     signers: T,
     writer: &mut W,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let rpc_client = rpc_clients.default();
     let (recent_blockhash, last_valid_block_height) =
         rpc_client.get_latest_blockhash_with_commitment(rpc_client.commitment())?;
-    let token = MaybeToken::SOL();
-    let stake_account = db
-        .get_account(stake_address, MaybeToken::SOL())
-        .ok_or_else(|| format!("SOL account does not exist for {stake_address}"))?;
-    if db.get_account(to_address, MaybeToken::SOL()).is_none() {
-        return Err(format!("Account {} ({}) does not exist", to_address, token,).into());
-    }
-    let (withdraw_all, amount) = match amount {
-        None => (true, stake_account.last_update_balance),
-        Some(amount) => (false, amount),
-    };
-    let instructions = vec![solana_sdk::stake::instruction::withdraw(
+    let instructions = vec![solana_sdk::stake::instruction::set_lockup(
         &stake_address,
-        &stake_authority,
-        &to_address,
-        amount,
-        custodian,
+        &lockup,
+        &custodian,
     )];
-    let message = Message::new(&instructions, Some(&stake_authority));
+    let message = Message::new(&instructions, Some(&fee_payer));
     let mut transaction = Transaction::new_unsigned(message);
     transaction.message.recent_blockhash = recent_blockhash;
     let simulation_result = rpc_client.simulate_transaction(&transaction)?.value;
@@ -1997,49 +5168,69 @@ pub async fn process_stake_withdraw<T: Signers, W: Write>(
     transaction.try_sign(&signers, recent_blockhash)?;
     let signature = transaction.signatures[0];
     writeln!(writer, "Transaction signature: {signature}")?;
-    db.record_transfer(
-        signature,
-        last_valid_block_height,
-        Some(amount),
-        stake_address,
-        token,
-        to_address,
-        token,
-        lot_selection_method,
-        lot_numbers,
-    )?;
     if !sys::send_transaction_until_expired(rpc_clients, &transaction, last_valid_block_height)
         .unwrap_or_default()
     {
-        db.cancel_transfer(signature)?;
-        return Err("Withdraw failed".into());
-    }
-    writeln!(writer, "Withdraw confirmed: {signature}")?;
-    let when = sys::rpc_client_utils::get_signature_date(rpc_client, signature).await?;
-    db.confirm_transfer(signature, when)?;
-    if withdraw_all {
-        let stake_account = db.get_account(stake_address, MaybeToken::SOL()).unwrap();
-        assert!(stake_account.lots.is_empty());
-        db.remove_account(stake_address, MaybeToken::SOL())?;
+        return Err("Set lockup failed".into());
     }
+    let (meta, _) = get_stake_meta(rpc_client, &stake_address)?;
+    writeln!(writer, "Lockup {}", format_lockup(&meta.lockup))?;
     Ok(())
 }
 
-pub async fn process_stake_delegate<T: Signers, W: Write>(
+fn move_instruction(
+    operation: Operation,
+    from_address: &Pubkey,
+    to_address: &Pubkey,
+    stake_authority: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    match operation {
+        Operation::MoveLamports => solana_sdk::stake::instruction::move_lamports(
+            from_address,
+            to_address,
+            stake_authority,
+            amount,
+        ),
+        _ => solana_sdk::stake::instruction::move_stake(
+            from_address,
+            to_address,
+            stake_authority,
+            amount,
+        ),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn process_stake_move<T: Signers, W: Write>(
+    db: &mut Db,
     rpc_clients: &RpcClients,
-    stake_address: Pubkey,
-    stake_authority: Pubkey,
+    operation: Operation,
+    from_address: Pubkey,
     to_address: Pubkey,
+    stake_authority: Pubkey,
+    amount: u64,
+    lot_selection_method: LotSelectionMethod,
+    lot_numbers: Option<HashSet<usize>>,
     signers: T,
     writer: &mut W,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let rpc_client = rpc_clients.default();
     let (recent_blockhash, last_valid_block_height) =
         rpc_client.get_latest_blockhash_with_commitment(rpc_client.commitment())?;
-    let instructions = vec![solana_sdk::stake::instruction::delegate_stake(
-        &stake_address,
-        &stake_authority,
+    let token = MaybeToken::SOL();
+    if db.get_account(from_address, token).is_none() {
+        return Err(format!("Account {} ({}) does not exist", from_address, token).into());
+    }
+    if db.get_account(to_address, token).is_none() {
+        return Err(format!("Account {} ({}) does not exist", to_address, token).into());
+    }
+    let instructions = vec![move_instruction(
+        operation,
+        &from_address,
         &to_address,
+        &stake_authority,
+        amount,
     )];
     let message = Message::new(&instructions, Some(&stake_authority));
     let mut transaction = Transaction::new_unsigned(message);
@@ -2051,11 +5242,26 @@ pub async fn process_stake_delegate<T: Signers, W: Write>(
     transaction.try_sign(&signers, recent_blockhash)?;
     let signature = transaction.signatures[0];
     writeln!(writer, "Transaction signature: {signature}")?;
+    db.record_transfer(
+        signature,
+        last_valid_block_height,
+        Some(amount),
+        from_address,
+        token,
+        to_address,
+        token,
+        lot_selection_method,
+        lot_numbers,
+    )?;
     if !sys::send_transaction_until_expired(rpc_clients, &transaction, last_valid_block_height)
         .unwrap_or_default()
     {
-        return Err("Delegate failed".into());
+        db.cancel_transfer(signature)?;
+        return Err(format!("{operation} failed").into());
     }
+    writeln!(writer, "{operation} confirmed: {signature}")?;
+    let when = sys::rpc_client_utils::get_signature_date(rpc_client, signature).await?;
+    db.confirm_transfer(signature, when)?;
     Ok(())
 }
 
@@ -2119,24 +5325,132 @@ pub async fn process_token_transfer<W: Write>(
 
 pub fn get_epoch_end_time(rpc_client: &RpcClient) -> Result<String, Box<dyn std::error::Error>> {
     let epoch_info = rpc_client.get_epoch_info()?;
-    let last_complete_block_slot = epoch_info.absolute_slot - epoch_info.slot_index - 1;
-    let block_time = rpc_client.get_block_time(last_complete_block_slot)?;
+    Ok(format_timestamp(get_epoch_end_timestamp(
+        rpc_client,
+        &epoch_info,
+    )?))
+}
+
+fn get_epoch_end_timestamp(
+    rpc_client: &RpcClient,
+    epoch_info: &solana_sdk::epoch_info::EpochInfo,
+) -> Result<i64, Box<dyn std::error::Error>> {
     let now_timestamp = std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
         .expect("invalid now timestamp")
         .as_secs();
+    // there is no previous epoch to measure from in epoch 0, and no progress
+    // to extrapolate from in the first slot, so the slot time is assumed
+    let last_complete_block_slot = epoch_info
+        .absolute_slot
+        .checked_sub(epoch_info.slot_index + 1);
+    let Some(last_complete_block_slot) =
+        last_complete_block_slot.filter(|_| epoch_info.slot_index > 0)
+    else {
+        let slots_left = epoch_info.slots_in_epoch - epoch_info.slot_index;
+        let ms_left = slots_left * solana_sdk::clock::DEFAULT_MS_PER_SLOT;
+        return Ok((now_timestamp + ms_left / 1000) as i64);
+    };
+    let block_time = rpc_client.get_block_time(last_complete_block_slot)?;
     let fraction_done = Decimal::from_u64(epoch_info.slot_index).unwrap()
         / Decimal::from_u64(epoch_info.slots_in_epoch).unwrap();
     let secs_since_epoch =
         Decimal::from_u64(now_timestamp).unwrap() - Decimal::from_i64(block_time).unwrap();
     let secs_to_epoch_end = secs_since_epoch / fraction_done - secs_since_epoch;
-    let end_epoch_timestamp = (Decimal::from_u64(now_timestamp).unwrap() + secs_to_epoch_end)
-        .to_i64()
-        .unwrap();
-    let epoch_end_datetime = DateTime::from_timestamp(end_epoch_timestamp, 0)
-        .expect("invalid timestamp")
-        .with_timezone(&Local);
-    Ok(format!("{}", epoch_end_datetime.format("%Y-%m-%d %H:%M")))
+    Ok(
+        (Decimal::from_u64(now_timestamp).unwrap() + secs_to_epoch_end)
+            .to_i64()
+            .unwrap(),
+    )
+}
+
+fn get_stake_history(
+    rpc_client: &RpcClient,
+) -> Result<
+    (
+        solana_sdk::stake_history::StakeHistory,
+        solana_sdk::clock::Clock,
+        Option<solana_sdk::clock::Epoch>,
+    ),
+    Box<dyn std::error::Error>,
+> {
+    let stake_history_account = rpc_client.get_account(&solana_sdk::sysvar::stake_history::id())?;
+    let stake_history: solana_sdk::stake_history::StakeHistory =
+        solana_sdk::account::from_account(&stake_history_account).unwrap();
+    let clock_account = rpc_client.get_account(&solana_sdk::sysvar::clock::id())?;
+    let clock: solana_sdk::clock::Clock =
+        solana_sdk::account::from_account(&clock_account).unwrap();
+    let new_rate_activation_epoch = rpc_client
+        .get_feature_activation_slot(&solana_sdk::feature_set::reduce_stake_warmup_cooldown::id())
+        .and_then(|activation_slot: Option<solana_sdk::clock::Slot>| {
+            rpc_client
+                .get_epoch_schedule()
+                .map(|epoch_schedule| (activation_slot, epoch_schedule))
+        })
+        .map(|(activation_slot, epoch_schedule)| {
+            activation_slot.map(|slot| epoch_schedule.get_epoch(slot))
+        })?;
+    Ok((stake_history, clock, new_rate_activation_epoch))
+}
+
+fn get_epoch_status(
+    rpc_client: &RpcClient,
+    accounts: &[TrackedAccount],
+) -> Result<EpochStatus, Box<dyn std::error::Error>> {
+    let epoch_info = rpc_client.get_epoch_info()?;
+    let end_timestamp = get_epoch_end_timestamp(rpc_client, &epoch_info)?;
+    let (stake_history, _, new_rate_activation_epoch) = get_stake_history(rpc_client)?;
+    let accounts = accounts
+        .iter()
+        .filter(|x| x.token.is_sol())
+        .collect::<Vec<_>>();
+    let mut transitions = vec![];
+    for chunk in accounts.chunks(100) {
+        let addresses = chunk.iter().map(|x| x.address).collect::<Vec<_>>();
+        let stake_accounts = rpc_client.get_multiple_accounts(&addresses)?;
+        for (account, stake_account) in chunk.iter().zip(stake_accounts) {
+            let Some(stake_account) = stake_account else {
+                continue;
+            };
+            if stake_account.owner != solana_sdk::stake::program::id() {
+                continue;
+            }
+            if let Ok(solana_sdk::stake::state::StakeStateV2::Stake(_, stake, _)) =
+                stake_account.state()
+            {
+                let solana_sdk::stake::state::StakeActivationStatus {
+                    activating,
+                    deactivating,
+                    ..
+                } = stake.delegation.stake_activating_and_deactivating(
+                    epoch_info.epoch,
+                    &stake_history,
+                    new_rate_activation_epoch,
+                );
+                if activating > 0 {
+                    transitions.push(format!(
+                        "{} activating {}",
+                        account.description,
+                        account.token.format_amount(activating)
+                    ));
+                }
+                if deactivating > 0 {
+                    transitions.push(format!(
+                        "{} deactivating {}, withdrawable after the boundary",
+                        account.description,
+                        account.token.format_amount(deactivating)
+                    ));
+                }
+            }
+        }
+    }
+    Ok(EpochStatus {
+        epoch: epoch_info.epoch,
+        slot_index: epoch_info.slot_index,
+        slots_in_epoch: epoch_info.slots_in_epoch,
+        end_timestamp,
+        transitions,
+    })
 }
 
 pub fn get_account_state<W: Write>(
@@ -2165,32 +5479,14 @@ pub fn get_account_state<W: Write>(
         if let Ok(stake_state) = account.state() {
             let sol = solana_sdk::native_token::lamports_to_sol(account.lamports);
             writeln!(writer, "Balance:            {sol:.9} SOL")?;
-            let stake_history_account =
-                rpc_client.get_account(&solana_sdk::sysvar::stake_history::id())?;
-            let stake_history: solana_sdk::stake_history::StakeHistory =
-                solana_sdk::account::from_account(&stake_history_account).unwrap();
-            let clock_account = rpc_client.get_account(&solana_sdk::sysvar::clock::id())?;
-            let clock: solana_sdk::clock::Clock =
-                solana_sdk::account::from_account(&clock_account).unwrap();
-            let new_rate_activation_epoch = rpc_client
-                .get_feature_activation_slot(
-                    &solana_sdk::feature_set::reduce_stake_warmup_cooldown::id(),
-                )
-                .and_then(|activation_slot: Option<solana_sdk::clock::Slot>| {
-                    rpc_client
-                        .get_epoch_schedule()
-                        .map(|epoch_schedule| (activation_slot, epoch_schedule))
-                })
-                .map(|(activation_slot, epoch_schedule)| {
-                    activation_slot.map(|slot| epoch_schedule.get_epoch(slot))
-                })?;
+            let (stake_history, clock, new_rate_activation_epoch) = get_stake_history(rpc_client)?;
 
             match stake_state {
                 solana_sdk::stake::state::StakeStateV2::Stake(
                     solana_sdk::stake::state::Meta {
                         rent_exempt_reserve: _,
                         authorized,
-                        lockup: _,
+                        lockup,
                     },
                     stake,
                     _,
@@ -2228,6 +5524,7 @@ pub fn get_account_state<W: Write>(
                             stake.delegation.voter_pubkey
                         )?;
                     }
+                    write_lockup(writer, &lockup, &clock)?;
                     if deactivating > 0 {
                         writeln!(
                             writer,
@@ -2252,12 +5549,15 @@ pub fn get_account_state<W: Write>(
                     solana_sdk::stake::state::Meta {
                         rent_exempt_reserve: _,
                         authorized,
-                        lockup: _,
+                        lockup,
                     },
                 ) => {
                     writeln!(writer, "Stake authority:    {}", authorized.staker)?;
                     writeln!(writer, "Withdraw authority: {}", authorized.withdrawer)?;
-                    writeln!(writer, "Stake is available for withdrawal now")?;
+                    write_lockup(writer, &lockup, &clock)?;
+                    if !lockup.is_in_force(&clock, None) {
+                        writeln!(writer, "Stake is available for withdrawal now")?;
+                    }
                 }
             }
         }
@@ -2267,6 +5567,140 @@ pub fn get_account_state<W: Write>(
     Ok(())
 }
 
+fn write_lockup<W: Write>(
+    writer: &mut W,
+    lockup: &solana_sdk::stake::state::Lockup,
+    clock: &solana_sdk::clock::Clock,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if *lockup == solana_sdk::stake::state::Lockup::default() {
+        return Ok(());
+    }
+    let until = format_timestamp(lockup.unix_timestamp);
+    writeln!(writer, "Lockup timestamp:   {until}")?;
+    writeln!(writer, "Lockup epoch:       {}", lockup.epoch)?;
+    writeln!(writer, "Lockup custodian:   {}", lockup.custodian)?;
+    if lockup.is_in_force(clock, None) {
+        writeln!(
+            writer,
+            "Lockup is in force, withdrawals need the custodian signature"
+        )?;
+    }
+    Ok(())
+}
+
+fn get_validators(
+    rpc_client: &RpcClient,
+    accounts: &[TrackedAccount],
+) -> Result<Vec<ValidatorInfo>, Box<dyn std::error::Error>> {
+    let vote_accounts = rpc_client.get_vote_accounts()?;
+    let addresses = accounts
+        .iter()
+        .filter(|x| x.token.is_sol())
+        .map(|x| x.address)
+        .collect::<Vec<_>>();
+    let mut delegations = HashMap::<Pubkey, usize>::new();
+    for chunk in addresses.chunks(100) {
+        for account in rpc_client
+            .get_multiple_accounts(chunk)?
+            .into_iter()
+            .flatten()
+        {
+            if account.owner != solana_sdk::stake::program::id() {
+                continue;
+            }
+            if let Ok(solana_sdk::stake::state::StakeStateV2::Stake(_, stake, _)) = account.state()
+            {
+                *delegations
+                    .entry(stake.delegation.voter_pubkey)
+                    .or_default() += 1;
+            }
+        }
+    }
+    let mut validators = vote_accounts
+        .current
+        .into_iter()
+        .map(|x| (x, false))
+        .chain(vote_accounts.delinquent.into_iter().map(|x| (x, true)))
+        .map(|(x, delinquent)| ValidatorInfo {
+            delegated: Pubkey::from_str(&x.vote_pubkey)
+                .ok()
+                .and_then(|x| delegations.get(&x).copied())
+                .unwrap_or_default(),
+            vote_pubkey: x.vote_pubkey,
+            node_pubkey: x.node_pubkey,
+            commission: x.commission,
+            activated_stake: x.activated_stake,
+            last_vote: x.last_vote,
+            delinquent,
+        })
+        .collect::<Vec<_>>();
+    validators.sort_by(|a, b| {
+        b.delegated
+            .cmp(&a.delegated)
+            .then(b.activated_stake.cmp(&a.activated_stake))
+    });
+    Ok(validators)
+}
+
+const REWARD_EPOCHS: u64 = 10;
+
+fn get_rewards(
+    rpc_client: &RpcClient,
+    accounts: &[TrackedAccount],
+    disposed: &[DisposedLot],
+    address: Pubkey,
+) -> Result<Vec<RewardInfo>, Box<dyn std::error::Error>> {
+    let epoch = rpc_client.get_epoch_info()?.epoch;
+    let epoch_schedule = rpc_client.get_epoch_schedule()?;
+    let epochs_per_year = 365.25 * 24. * 60. * 60. * 1000.
+        / (epoch_schedule.slots_per_epoch * solana_sdk::clock::DEFAULT_MS_PER_SLOT) as f64;
+    let account_lots = accounts
+        .iter()
+        .filter(|x| x.address == address && x.token.is_sol())
+        .flat_map(|x| x.lots.iter())
+        .collect::<Vec<_>>();
+    let other_lots = accounts
+        .iter()
+        .filter(|x| x.address != address && x.token.is_sol())
+        .flat_map(|x| x.lots.iter().cloned())
+        .chain(disposed.iter().map(|x| x.lot.clone()))
+        .collect::<Vec<_>>();
+    let mut rewards = vec![];
+    for epoch in (epoch.saturating_sub(REWARD_EPOCHS)..epoch).rev() {
+        let reward = rpc_client
+            .get_inflation_reward(&[address], Some(epoch))?
+            .into_iter()
+            .next()
+            .flatten();
+        if let Some(reward) = reward {
+            let reward_epoch = epoch;
+            let is_reward = |lot: &Lot| matches!(lot.acquisition.kind, LotAcquistionKind::EpochReward { epoch, .. } if epoch == reward_epoch);
+            let lot = if let Some(lot) = account_lots.iter().find(|x| is_reward(x)) {
+                Some(format!("{}", lot.lot_number))
+            } else {
+                other_lots
+                    .iter()
+                    .find(|x| is_reward(x) && x.amount == reward.amount)
+                    .map(|x| format!("{} (moved)", x.lot_number))
+            };
+            let pre_balance = reward.post_balance.saturating_sub(reward.amount);
+            let apy = (pre_balance > 0).then(|| {
+                let rate = reward.amount as f64 / pre_balance as f64;
+                ((1. + rate).powf(epochs_per_year) - 1.) * 100.
+            });
+            rewards.push(RewardInfo {
+                epoch,
+                amount: reward.amount,
+                post_balance: reward.post_balance,
+                commission: reward.commission,
+                apy,
+                lot,
+            });
+        }
+    }
+    Ok(rewards)
+}
+
 fn get_account_balance(
     rpc_clients: &RpcClients,
     address: Pubkey,
@@ -2294,3 +5728,256 @@ fn adjust_balance(db: &mut Db, address: Pubkey) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lot(lot_number: usize, when: &str, price: &str, amount: u64) -> Lot {
+        Lot {
+            lot_number,
+            acquisition: LotAcquistion::new(
+                NaiveDate::parse_from_str(when, "%Y-%m-%d").unwrap(),
+                price.parse().unwrap(),
+                LotAcquistionKind::NotAvailable,
+            ),
+            amount,
+        }
+    }
+
+    /// Lots of a SOL and a USDC account.
+    fn sample() -> Vec<(MaybeToken, Lot)> {
+        let usdc = MaybeToken::from(Some(Token::USDC));
+        vec![
+            (
+                MaybeToken::SOL(),
+                lot(1, "2023-03-01", "21.38", 1_500_000_000),
+            ),
+            (
+                MaybeToken::SOL(),
+                lot(2, "2024-02-29", "101.13", 333_333_333),
+            ),
+            (
+                MaybeToken::SOL(),
+                lot(3, "2024-11-20", "243.9", 12_000_000_001),
+            ),
+            (usdc, lot(4, "2024-06-01", "0.9998", 2_500_000_000)),
+        ]
+    }
+
+    #[test]
+    fn ui_amount_agrees_with_sys() {
+        for (token, lot) in sample() {
+            assert_eq!(
+                ui_amount(token, lot.amount).to_f64().unwrap(),
+                token.ui_amount(lot.amount)
+            );
+        }
+    }
+
+    /// The UI total agrees with the sum of the per lot figures of sys, which
+    /// are floats.
+    fn assert_agrees(total: Decimal, sys_total: f64) {
+        assert!(
+            (total - to_decimal(sys_total)).abs() < Decimal::new(1, 6),
+            "{total} != {sys_total}"
+        );
+    }
+
+    #[test]
+    fn totals_agree_with_sys() {
+        let dir = std::env::temp_dir().join(format!("sys-ui-db-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut db = db::new(&dir).unwrap();
+        let sol = MaybeToken::SOL();
+        let address = Pubkey::new_unique();
+        let lots = sample()
+            .into_iter()
+            .filter(|x| x.0 == sol)
+            .map(|x| x.1)
+            .collect::<Vec<_>>();
+        db.add_account(TrackedAccount {
+            address,
+            token: sol,
+            description: "Test account".to_string(),
+            last_update_epoch: 0,
+            last_update_balance: lots.iter().map(|x| x.amount).sum(),
+            lots,
+            no_sync: None,
+        })
+        .unwrap();
+        // all of lot 1 short-term at $23.47, a gain of $3.135, then lot 2
+        // and part of lot 3 long-term
+        db.record_disposal(
+            address,
+            sol,
+            1_500_000_000,
+            "Test sale".to_string(),
+            NaiveDate::from_ymd_opt(2024, 3, 1).unwrap(),
+            Decimal::new(2347, 2),
+            LotSelectionMethod::FirstInFirstOut,
+            None,
+        )
+        .unwrap();
+        db.record_disposal(
+            address,
+            sol,
+            2_333_333_333,
+            "Test sale".to_string(),
+            NaiveDate::from_ymd_opt(2025, 12, 1).unwrap(),
+            Decimal::new(18_905, 2),
+            LotSelectionMethod::FirstInFirstOut,
+            Some(HashSet::from([2, 3])),
+        )
+        .unwrap();
+
+        let disposed = db.disposed_lots();
+        assert_eq!(disposed.len(), 3);
+        let (amount, value, income, short_gain, long_gain) = disposed_totals(&disposed, |_| true);
+        assert_eq!(amount, 3_833_333_333);
+        assert_eq!(
+            value,
+            ui_amount(sol, 1_500_000_000) * Decimal::new(2347, 2)
+                + ui_amount(sol, 2_333_333_333) * Decimal::new(18_905, 2)
+        );
+        assert_agrees(income, disposed.iter().map(|x| x.lot.income(x.token)).sum());
+        let sys_gain = |long_term: bool| {
+            disposed
+                .iter()
+                .filter(|x| holding::is_long_term(x.lot.acquisition.when, x.when) == long_term)
+                .map(|x| x.lot.cap_gain(x.token, x.price()))
+                .sum::<f64>()
+        };
+        assert_agrees(short_gain, sys_gain(false));
+        assert_agrees(long_gain, sys_gain(true));
+        assert_eq!(usd(short_gain), "3.14");
+        assert_eq!(disposed_totals(&disposed, |x| *x == 1).3, short_gain);
+
+        let account = db.get_account(address, sol).unwrap();
+        let price = Decimal::from(150);
+        let today = NaiveDate::from_ymd_opt(2025, 12, 1).unwrap();
+        let (amount, cost, short_gain, long_gain) =
+            lot_totals(sol, account.lots.iter(), price, today);
+        assert_eq!(amount, 10_000_000_001);
+        assert_eq!(
+            cost,
+            ui_amount(sol, amount) * price - short_gain - long_gain
+        );
+        let sys_gain = |long_term: bool| {
+            account
+                .lots
+                .iter()
+                .filter(|x| holding::is_long_term(x.acquisition.when, today) == long_term)
+                .map(|x| x.cap_gain(sol, price))
+                .sum::<f64>()
+        };
+        assert_agrees(short_gain, sys_gain(false));
+        assert_agrees(long_gain, sys_gain(true));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn usd_rounds_half_cents_away_from_zero() {
+        // 1.5 SOL at $23.47 is $35.205, which is just below in floats
+        let basis = ui_amount(MaybeToken::SOL(), 1_500_000_000) * Decimal::new(2347, 2);
+        assert_eq!(usd(basis), "35.21");
+        assert_eq!(usd(-basis), "-35.21");
+        assert_eq!(usd(Decimal::new(-4, 3)), "0.00");
+        assert_eq!(usd(Decimal::new(123456789012, 4)), "12,345,678.90");
+        assert_eq!(usd(Decimal::new(100000, 2)), "1,000.00");
+    }
+
+    #[test]
+    fn estimate_tax_offsets_losses() {
+        let rates = TaxRates {
+            income: Decimal::new(30, 2),
+            short_term_gain: Decimal::new(30, 2),
+            long_term_gain: Decimal::new(15, 2),
+        };
+        let tax =
+            |short: i64, long: i64| estimate_tax(Decimal::from(short), Decimal::from(long), &rates);
+        assert_eq!(tax(100, 200), Decimal::from(60));
+        assert_eq!(tax(-100, 200), Decimal::from(15));
+        assert_eq!(tax(300, -100), Decimal::from(60));
+        assert_eq!(tax(-300, 100), Decimal::ZERO);
+    }
+
+    fn simulate(selected: &[usize], amount: u64, method: LotMethod) -> Result<Vec<String>, String> {
+        let lots = sample()
+            .into_iter()
+            .filter(|x| x.0 == MaybeToken::SOL())
+            .map(|x| x.1)
+            .collect::<Vec<_>>();
+        let rates = TaxRates {
+            income: Decimal::new(30, 2),
+            short_term_gain: Decimal::new(30, 2),
+            long_term_gain: Decimal::new(15, 2),
+        };
+        simulate_sale(
+            MaybeToken::SOL(),
+            &lots,
+            &selected.iter().copied().collect(),
+            amount,
+            Decimal::from(150),
+            NaiveDate::from_ymd_opt(2025, 6, 1).unwrap(),
+            method,
+            &rates,
+        )
+        .map(|x| {
+            x.lots
+                .iter()
+                .map(|x| x.split(' ').next().unwrap().to_string())
+                .collect()
+        })
+    }
+
+    #[test]
+    fn simulate_without_selection() {
+        assert_eq!(
+            simulate(&[], 1_700_000_000, LotMethod::Fifo),
+            Ok(vec!["1".to_string(), "2".to_string()])
+        );
+        assert_eq!(
+            simulate(&[], 1_700_000_000, LotMethod::Hifo),
+            Ok(vec!["3".to_string()])
+        );
+        assert!(simulate(&[], 1_700_000_000, LotMethod::SpecificId).is_err());
+    }
+
+    #[test]
+    fn simulate_with_lots_of_other_accounts_selected() {
+        // lot 4 is a USDC lot, the other methods ignore the selection
+        assert_eq!(
+            simulate(&[2, 4], 1_700_000_000, LotMethod::Fifo),
+            Ok(vec!["1".to_string(), "2".to_string()])
+        );
+        assert_eq!(
+            simulate(&[2, 4], 300_000_000, LotMethod::SpecificId),
+            Ok(vec!["2".to_string()])
+        );
+        assert!(simulate(&[2, 4], 1_700_000_000, LotMethod::SpecificId).is_err());
+        assert!(simulate(&[4], 300_000_000, LotMethod::SpecificId).is_err());
+    }
+
+    #[test]
+    fn export_path_keeps_earlier_exports() {
+        let dir = std::env::temp_dir().join(format!("sys-ui-export-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        assert_eq!(
+            export_path(&dir, "form8949-2024"),
+            dir.join("form8949-2024.csv")
+        );
+        std::fs::write(dir.join("form8949-2024.csv"), "").unwrap();
+        std::fs::write(dir.join("form8949-2024-1.csv"), "").unwrap();
+        assert_eq!(
+            export_path(&dir, "form8949-2024"),
+            dir.join("form8949-2024-2.csv")
+        );
+        assert_eq!(
+            export_path(&dir, "form8949-2023"),
+            dir.join("form8949-2023.csv")
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,286 @@
+use {
+    chrono::prelude::*,
+    rust_decimal::prelude::*,
+    serde::{Deserialize, Serialize},
+    solana_client::rpc_client::RpcClient,
+    solana_pubkey::Pubkey,
+    std::{collections::HashMap, str::FromStr},
+    sys::{exchange::*, token::*},
+};
+
+/// Prices older than this are marked stale in the UI.
+pub const STALE_PRICE_SECS: i64 = 5 * 60;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Price {
+    pub value: Decimal,
+    pub source: String,
+    pub when: DateTime<Utc>,
+}
+
+impl Price {
+    pub fn age(&self) -> i64 {
+        Utc::now().signed_duration_since(self.when).num_seconds()
+    }
+
+    pub fn is_stale(&self) -> bool {
+        self.age() > STALE_PRICE_SECS
+    }
+
+    pub fn describe(&self) -> String {
+        format!(
+            "{} at {}",
+            self.source,
+            self.when.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S")
+        )
+    }
+}
+
+/// One link of the price fallback chain configured in `price_sources`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "kind")]
+pub enum PriceSource {
+    /// Spot price from one of the exchange clients configured in the sys db.
+    Exchange { exchange: Exchange },
+    /// Quote from the Jupiter price API.
+    Dex {
+        #[serde(default = "default_dex_url")]
+        url: String,
+    },
+    /// Pyth price update accounts, keyed by token name.
+    Oracle { accounts: HashMap<String, String> },
+    /// YAML map of token name to USD price, for working offline.
+    File { path: String },
+}
+
+fn default_dex_url() -> String {
+    "https://lite-api.jup.ag/price/v2".to_string()
+}
+
+pub fn default_price_sources() -> Vec<PriceSource> {
+    vec![
+        PriceSource::Exchange {
+            exchange: Exchange::Coinbase,
+        },
+        PriceSource::Dex {
+            url: default_dex_url(),
+        },
+    ]
+}
+
+impl std::fmt::Display for PriceSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PriceSource::Exchange { exchange } => write!(f, "{exchange:?}"),
+            PriceSource::Dex { .. } => write!(f, "Jupiter"),
+            PriceSource::Oracle { .. } => write!(f, "Pyth"),
+            PriceSource::File { path } => write!(f, "{path}"),
+        }
+    }
+}
+
+/// Tries every source in order and returns the first price found, or the
+/// reasons each source failed.
+pub async fn get_price(
+    sources: &[PriceSource],
+    token: &MaybeToken,
+    rpc_client: &RpcClient,
+    xclients: &HashMap<Exchange, Box<dyn ExchangeClient>>,
+) -> Result<Price, String> {
+    let mut errors = vec![];
+    for source in sources {
+        let result = match source {
+            PriceSource::Exchange { exchange } => match xclients.get(exchange) {
+                Some(exchange_client) => token
+                    .get_spot_price(exchange_client.as_ref(), None)
+                    .await
+                    .map_err(|e| e.to_string())
+                    .and_then(|x| {
+                        Decimal::from_f64(x)
+                            .map(|value| (value.round_dp(6).normalize(), Utc::now()))
+                            .ok_or_else(|| format!("invalid price {x}"))
+                    }),
+                None => Err("no exchange client".to_string()),
+            },
+            PriceSource::Dex { url } => get_dex_price(url, token).await,
+            PriceSource::Oracle { accounts } => match accounts.get(&token.to_string()) {
+                Some(address) => get_oracle_price(rpc_client, address),
+                None => Err("no oracle account".to_string()),
+            },
+            PriceSource::File { path } => get_file_price(path, token),
+        };
+        match result {
+            Ok((value, when)) => {
+                return Ok(Price {
+                    value,
+                    source: source.to_string(),
+                    when,
+                })
+            }
+            Err(e) => errors.push(format!("{source}: {e}")),
+        }
+    }
+    Err(errors.join(", "))
+}
+
+async fn get_dex_price(url: &str, token: &MaybeToken) -> Result<(Decimal, DateTime<Utc>), String> {
+    let mint = token.mint();
+    let response = reqwest::get(format!("{url}?ids={mint}"))
+        .await
+        .map_err(|e| e.to_string())?
+        .json::<serde_json::Value>()
+        .await
+        .map_err(|e| e.to_string())?;
+    response["data"][mint.to_string()]["price"]
+        .as_str()
+        .and_then(|x| x.parse::<Decimal>().ok())
+        .map(|value| (value, Utc::now()))
+        .ok_or_else(|| format!("no quote for {mint}"))
+}
+
+fn get_oracle_price(
+    rpc_client: &RpcClient,
+    address: &str,
+) -> Result<(Decimal, DateTime<Utc>), String> {
+    let address = Pubkey::from_str(address).map_err(|e| e.to_string())?;
+    let data = rpc_client
+        .get_account_data(&address)
+        .map_err(|e| e.to_string())?;
+    decode_price_update(&data).map_err(|e| format!("{address} {e}"))
+}
+
+/// Decodes the data of a Pyth receiver `PriceUpdateV2` account.
+fn decode_price_update(data: &[u8]) -> Result<(Decimal, DateTime<Utc>), String> {
+    // discriminator and write authority, then the verification level which
+    // carries the number of signatures only when partial
+    let offset = match data.get(40) {
+        Some(0) => 42,
+        Some(1) => 41,
+        _ => return Err("is not a price update account".to_string()),
+    };
+    // feed id, then price, confidence, exponent and publish time
+    let field = |start: usize, len: usize| {
+        data.get(offset + 32 + start..offset + 32 + start + len)
+            .ok_or_else(|| "is too short".to_string())
+    };
+    let price = i64::from_le_bytes(field(0, 8)?.try_into().unwrap());
+    let exponent = i32::from_le_bytes(field(16, 4)?.try_into().unwrap());
+    let publish_time = i64::from_le_bytes(field(20, 8)?.try_into().unwrap());
+    let when = DateTime::from_timestamp(publish_time, 0)
+        .ok_or_else(|| format!("invalid publish time {publish_time}"))?;
+    let value = if exponent <= 0 {
+        Decimal::try_new(price, exponent.unsigned_abs()).map_err(|e| e.to_string())?
+    } else {
+        10u64
+            .checked_pow(exponent as u32)
+            .and_then(|x| Decimal::from(price).checked_mul(Decimal::from(x)))
+            .ok_or_else(|| format!("price {price}e{exponent} is out of range"))?
+    };
+    Ok((value, when))
+}
+
+fn get_file_price(path: &str, token: &MaybeToken) -> Result<(Decimal, DateTime<Utc>), String> {
+    let file = std::fs::File::open(path).map_err(|e| e.to_string())?;
+    let when = file
+        .metadata()
+        .and_then(|x| x.modified())
+        .map(DateTime::<Utc>::from)
+        .map_err(|e| e.to_string())?;
+    let prices: HashMap<String, serde_yaml::Number> =
+        serde_yaml::from_reader(file).map_err(|e| e.to_string())?;
+    let value = prices
+        .get(&token.to_string())
+        .ok_or_else(|| format!("no price for {token}"))?
+        .to_string();
+    value
+        .parse::<Decimal>()
+        .or_else(|_| Decimal::from_scientific(&value))
+        .map(|value| (value, when))
+        .map_err(|e| format!("invalid price {value} for {token}: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Account data of a price update, `level` being the verification level
+    /// with the number of signatures when partial.
+    fn price_update(level: &[u8], price: i64, exponent: i32, publish_time: i64) -> Vec<u8> {
+        let mut data = vec![0; 40];
+        data.extend(level);
+        data.extend([7; 32]);
+        data.extend(price.to_le_bytes());
+        data.extend(12u64.to_le_bytes());
+        data.extend(exponent.to_le_bytes());
+        data.extend(publish_time.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn decode_full_verification() {
+        let data = price_update(&[1], 14_250_000_000, -8, 1_700_000_000);
+        let (value, when) = decode_price_update(&data).unwrap();
+        assert_eq!(value, Decimal::new(14_250, 2));
+        assert_eq!(when.timestamp(), 1_700_000_000);
+    }
+
+    #[test]
+    fn decode_partial_verification() {
+        let data = price_update(&[0, 5], 99_987, -5, 1_700_000_060);
+        let (value, when) = decode_price_update(&data).unwrap();
+        assert_eq!(value, Decimal::new(99_987, 5));
+        assert_eq!(when.timestamp(), 1_700_000_060);
+    }
+
+    #[test]
+    fn decode_positive_exponent() {
+        let data = price_update(&[1], -3, 2, 1_700_000_000);
+        assert_eq!(decode_price_update(&data).unwrap().0, Decimal::from(-300));
+        let data = price_update(&[1], 7, 19, 1_700_000_000);
+        assert_eq!(
+            decode_price_update(&data).unwrap().0,
+            Decimal::from_i128_with_scale(7 * 10i128.pow(19), 0)
+        );
+        let data = price_update(&[1], 1, 20, 1_700_000_000);
+        assert!(decode_price_update(&data).is_err());
+        let data = price_update(&[1], i64::MAX, 19, 1_700_000_000);
+        assert!(decode_price_update(&data).is_err());
+    }
+
+    #[test]
+    fn decode_invalid() {
+        let data = price_update(&[1], 1, 0, 1_700_000_000);
+        assert!(decode_price_update(&data[..data.len() - 1]).is_err());
+        assert!(decode_price_update(&data[..40]).is_err());
+        let mut data = data;
+        data[40] = 2;
+        assert!(decode_price_update(&data).is_err());
+    }
+
+    fn file_price(name: &str, contents: &str) -> Result<Decimal, String> {
+        let path =
+            std::env::temp_dir().join(format!("sys-ui-price-{}-{name}.yml", std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        let result = get_file_price(path.to_str().unwrap(), &MaybeToken::SOL());
+        std::fs::remove_file(&path).unwrap();
+        result.map(|x| x.0)
+    }
+
+    #[test]
+    fn file_prices() {
+        let sol = MaybeToken::SOL();
+        assert_eq!(
+            file_price("integer", &format!("{sol}: 142\n")),
+            Ok(Decimal::from(142))
+        );
+        assert_eq!(
+            file_price("decimal", &format!("{sol}: 142.37\n")),
+            Ok(Decimal::new(14_237, 2))
+        );
+        assert_eq!(
+            file_price("scientific", &format!("{sol}: 1.5e-3\n")),
+            Ok(Decimal::new(15, 4))
+        );
+        assert!(file_price("missing", "USDC: 1.0\n").is_err());
+    }
+}
@@ -0,0 +1,401 @@
+use {
+    chrono::prelude::*,
+    dioxus::prelude::*,
+    rust_decimal::prelude::*,
+    std::{
+        cmp::Ordering,
+        collections::{BTreeMap, BTreeSet},
+    },
+};
+
+/// Width in pixels a column starts resizing from when it has not been
+/// resized yet.
+const DEFAULT_WIDTH: f64 = 100.;
+const MIN_WIDTH: f64 = 30.;
+
+/// Value a column sorts by.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SortKey {
+    Number(Decimal),
+    Date(NaiveDate),
+    Text(String),
+}
+
+/// A row of a `Table`.
+pub trait TableRow: Clone + PartialEq + 'static {
+    /// Identifies the row in the selection.
+    fn id(&self) -> usize;
+
+    /// Class of the row when it is not selected.
+    fn class(&self) -> &'static str {
+        "regular"
+    }
+
+    fn title(&self) -> String {
+        String::new()
+    }
+}
+
+pub struct Column<T> {
+    pub id: &'static str,
+    pub title: &'static str,
+    pub class: &'static str,
+    pub key: fn(&T) -> SortKey,
+    pub cell: fn(&T) -> String,
+    pub tooltip: Option<fn(&T) -> String>,
+}
+
+impl<T> Column<T> {
+    pub fn new(
+        id: &'static str,
+        title: &'static str,
+        key: fn(&T) -> SortKey,
+        cell: fn(&T) -> String,
+    ) -> Self {
+        Self {
+            id,
+            title,
+            class: "",
+            key,
+            cell,
+            tooltip: None,
+        }
+    }
+
+    pub fn with_class(self, class: &'static str) -> Self {
+        Self { class, ..self }
+    }
+
+    pub fn with_tooltip(self, tooltip: fn(&T) -> String) -> Self {
+        Self {
+            tooltip: Some(tooltip),
+            ..self
+        }
+    }
+}
+
+impl<T> Clone for Column<T> {
+    fn clone(&self) -> Self {
+        Self { ..*self }
+    }
+}
+
+impl<T> PartialEq for Column<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id && self.title == other.title && self.class == other.class
+    }
+}
+
+/// Sort order, hidden columns and column widths of a table, kept in the
+/// global state so they survive switching pages.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TableState {
+    /// Sort columns, most significant first, and whether each is ascending.
+    pub sort: Vec<(&'static str, bool)>,
+    pub hidden: BTreeSet<&'static str>,
+    /// Widths in pixels of the columns resized by dragging their edge.
+    pub widths: BTreeMap<&'static str, f64>,
+    /// Column being resized, with the pointer position and the width when
+    /// the drag started.
+    resizing: Option<(&'static str, f64, f64)>,
+}
+
+impl TableState {
+    /// Sorts by the column alone, or by the column after the current sort
+    /// columns when `add` is set. Sorting by a sort column again reverses it.
+    pub fn sort_by(&mut self, column: &'static str, add: bool) {
+        match self.sort.iter().position(|x| x.0 == column) {
+            Some(i) if add || self.sort.len() == 1 => self.sort[i].1 = !self.sort[i].1,
+            None if add => self.sort.push((column, true)),
+            _ => self.sort = vec![(column, true)],
+        }
+    }
+
+    pub fn sort<T>(&self, columns: &[Column<T>], rows: &mut [T]) {
+        let keys = self
+            .sort
+            .iter()
+            .filter_map(|(id, ascending)| {
+                columns
+                    .iter()
+                    .find(|x| x.id == *id)
+                    .map(|x| (x.key, *ascending))
+            })
+            .collect::<Vec<_>>();
+        rows.sort_by(|a, b| {
+            keys.iter()
+                .map(|(key, ascending)| {
+                    let ordering = key(a).cmp(&key(b));
+                    if *ascending {
+                        ordering
+                    } else {
+                        ordering.reverse()
+                    }
+                })
+                .find(|x| x.is_ne())
+                .unwrap_or(Ordering::Equal)
+        });
+    }
+
+    /// Arrow of the column in the header, numbered when sorting by several.
+    fn indicator(&self, column: &str) -> String {
+        match self.sort.iter().position(|x| x.0 == column) {
+            Some(i) => format!(
+                " {}{}",
+                if self.sort[i].1 { "▲" } else { "▼" },
+                if self.sort.len() > 1 {
+                    (i + 1).to_string()
+                } else {
+                    String::new()
+                }
+            ),
+            None => String::new(),
+        }
+    }
+
+    fn style(&self, column: &str) -> String {
+        self.widths
+            .get(column)
+            .map(|x| format!("width: {x}px; min-width: {x}px; max-width: {x}px;"))
+            .unwrap_or_default()
+    }
+}
+
+/// Click selects the row alone, meta-click toggles it and shift-click
+/// extends the selection to it in display order, or selects it alone when
+/// nothing is selected.
+fn select(selected: &mut BTreeSet<usize>, ids: &[usize], id: usize, shift: bool, meta: bool) {
+    if shift && !selected.is_empty() {
+        let mut sel_end = 0;
+        let mut sel_beg = 0;
+        for (i, x) in ids.iter().enumerate() {
+            if *x == id {
+                sel_end = i;
+            } else if selected.contains(x) {
+                sel_beg = i;
+            }
+        }
+        if sel_beg > sel_end {
+            std::mem::swap(&mut sel_beg, &mut sel_end);
+        }
+        selected.extend(&ids[sel_beg..=sel_end]);
+    } else if meta {
+        if selected.contains(&id) {
+            selected.remove(&id);
+        } else {
+            selected.insert(id);
+        }
+    } else {
+        selected.clear();
+        selected.insert(id);
+    }
+}
+
+/// Table of selectable rows. Clicking a header sorts by the column and
+/// shift-clicking adds it to the sort columns. The edge of a header drags
+/// to resize the column, and the Columns list shows and hides columns.
+#[component]
+pub fn Table<T: TableRow>(
+    id: String,
+    columns: Vec<Column<T>>,
+    rows: Vec<T>,
+    state: Signal<TableState>,
+    selected: Signal<BTreeSet<usize>>,
+) -> Element {
+    let mut state = state;
+    let mut selected = selected;
+    let table = state.read().clone();
+    let mut rows = rows;
+    table.sort(&columns, &mut rows);
+    let ids = rows.iter().map(|x| x.id()).collect::<Vec<_>>();
+    let visible = columns
+        .iter()
+        .filter(|x| !table.hidden.contains(x.id))
+        .cloned()
+        .collect::<Vec<_>>();
+    rsx! {
+        div { id,
+            details { class: "columns",
+                summary { "Columns" }
+                for column in columns {
+                    label { key: "{column.id}",
+                        input {
+                            r#type: "checkbox",
+                            checked: !table.hidden.contains(column.id),
+                            onchange: move |event: Event<FormData>| {
+                                if event.checked() {
+                                    state.write().hidden.remove(column.id);
+                                } else {
+                                    state.write().hidden.insert(column.id);
+                                }
+                            },
+                        }
+                        "{column.title}"
+                    }
+                }
+            }
+            table {
+                onmousemove: move |event: Event<MouseData>| {
+                    let resizing = state.read().resizing;
+                    if let Some((column, x, width)) = resizing {
+                        let width = (width + event.client_coordinates().x - x).max(MIN_WIDTH);
+                        state.write().widths.insert(column, width);
+                    }
+                },
+                onmouseup: move |_| state.write().resizing = None,
+                onmouseleave: move |_| state.write().resizing = None,
+                thead {
+                    tr {
+                        for column in visible.iter().cloned() {
+                            th {
+                                key: "{column.id}",
+                                class: column.class,
+                                style: table.style(column.id),
+                                onclick: move |event: Event<MouseData>| {
+                                    let add = event.data().modifiers().shift();
+                                    state.write().sort_by(column.id, add);
+                                },
+                                {format!("{}{}", column.title, table.indicator(column.id))}
+                                span {
+                                    class: "resize",
+                                    onclick: move |event: Event<MouseData>| event.stop_propagation(),
+                                    onmousedown: move |event: Event<MouseData>| {
+                                        event.stop_propagation();
+                                        let width = state
+                                            .read()
+                                            .widths
+                                            .get(column.id)
+                                            .copied()
+                                            .unwrap_or(DEFAULT_WIDTH);
+                                        state.write().resizing =
+                                            Some((column.id, event.client_coordinates().x, width));
+                                    },
+                                }
+                            }
+                        }
+                    }
+                }
+                tbody {
+                    for row in rows {
+                        tr {
+                            key: "{row.id()}",
+                            class: if selected.read().contains(&row.id()) { "selected" } else { row.class() },
+                            title: row.title(),
+                            onclick: {
+                                let ids = ids.clone();
+                                let id = row.id();
+                                move |event: Event<MouseData>| {
+                                    let modifiers = event.data().modifiers();
+                                    select(
+                                        &mut selected.write(),
+                                        &ids,
+                                        id,
+                                        modifiers.shift(),
+                                        modifiers.meta(),
+                                    );
+                                }
+                            },
+                            for column in visible.iter() {
+                                td {
+                                    key: "{column.id}",
+                                    class: column.class,
+                                    style: table.style(column.id),
+                                    title: column.tooltip.map(|x| x(&row)).unwrap_or_default(),
+                                    {(column.cell)(&row)}
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Row {
+        id: usize,
+        name: &'static str,
+        amount: i64,
+    }
+
+    fn columns() -> Vec<Column<Row>> {
+        vec![
+            Column::new(
+                "name",
+                "Name",
+                |x| SortKey::Text(x.name.to_string()),
+                |x| x.name.to_string(),
+            ),
+            Column::new(
+                "amount",
+                "Amount",
+                |x| SortKey::Number(x.amount.into()),
+                |x| x.amount.to_string(),
+            ),
+        ]
+    }
+
+    fn rows() -> Vec<Row> {
+        [(1, "b", 20), (2, "a", 10), (3, "b", 10), (4, "a", 30)]
+            .into_iter()
+            .map(|(id, name, amount)| Row { id, name, amount })
+            .collect()
+    }
+
+    fn ids(rows: &[Row]) -> Vec<usize> {
+        rows.iter().map(|x| x.id).collect()
+    }
+
+    #[test]
+    fn sort_by_toggles() {
+        let mut state = TableState::default();
+        state.sort_by("name", false);
+        assert_eq!(state.sort, vec![("name", true)]);
+        state.sort_by("name", false);
+        assert_eq!(state.sort, vec![("name", false)]);
+        state.sort_by("amount", false);
+        assert_eq!(state.sort, vec![("amount", true)]);
+    }
+
+    #[test]
+    fn sort_by_adds() {
+        let mut state = TableState::default();
+        state.sort_by("name", false);
+        state.sort_by("amount", true);
+        assert_eq!(state.sort, vec![("name", true), ("amount", true)]);
+        state.sort_by("amount", true);
+        assert_eq!(state.sort, vec![("name", true), ("amount", false)]);
+        // a plain click on one of several sort columns sorts by it alone
+        state.sort_by("amount", false);
+        assert_eq!(state.sort, vec![("amount", true)]);
+    }
+
+    #[test]
+    fn sort_by_several_columns() {
+        let mut state = TableState::default();
+        state.sort = vec![("name", true), ("amount", false)];
+        let mut rows = rows();
+        state.sort(&columns(), &mut rows);
+        assert_eq!(ids(&rows), vec![4, 2, 1, 3]);
+        state.sort = vec![("amount", true), ("name", false)];
+        state.sort(&columns(), &mut rows);
+        assert_eq!(ids(&rows), vec![3, 2, 1, 4]);
+    }
+
+    #[test]
+    fn shift_click_selects() {
+        let ids = [4, 2, 1, 3];
+        let mut selected = BTreeSet::new();
+        select(&mut selected, &ids, 1, true, false);
+        assert_eq!(selected, BTreeSet::from([1]));
+        select(&mut selected, &ids, 4, true, false);
+        assert_eq!(selected, BTreeSet::from([1, 2, 4]));
+        select(&mut selected, &ids, 3, false, false);
+        assert_eq!(selected, BTreeSet::from([3]));
+    }
+}
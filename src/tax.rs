@@ -0,0 +1,395 @@
+use {
+    crate::holding::is_long_term,
+    chrono::prelude::*,
+    rust_decimal::prelude::*,
+    std::{collections::BTreeMap, fmt::Write},
+    sys::{
+        db::{DisposedLot, Lot, LotAcquistionKind},
+        token::*,
+    },
+};
+
+/// Days before and after a loss sale in which a purchase makes it a wash sale.
+pub const WASH_SALE_DAYS: i64 = 30;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct WashSale {
+    /// The disposed lot sold at a loss.
+    pub lot_number: usize,
+    pub disallowed_loss: Decimal,
+    /// Replacement lots and the disallowed loss added to their basis.
+    pub replacements: Vec<(usize, Decimal)>,
+}
+
+/// A disposed lot as far as wash sales are concerned.
+#[derive(Clone, Debug)]
+pub struct Sale<'a> {
+    pub token: MaybeToken,
+    pub lot: &'a Lot,
+    pub when: NaiveDate,
+    pub price: Decimal,
+}
+
+impl<'a> From<&'a DisposedLot> for Sale<'a> {
+    fn from(lot: &'a DisposedLot) -> Self {
+        Self {
+            token: lot.token,
+            lot: &lot.lot,
+            when: lot.when,
+            price: lot.price(),
+        }
+    }
+}
+
+fn is_purchase(lot: &Lot) -> bool {
+    !matches!(
+        lot.acquisition.kind,
+        LotAcquistionKind::NotAvailable | LotAcquistionKind::EpochReward { .. }
+    )
+}
+
+/// Purchases of the token within `WASH_SALE_DAYS` of a sale on `date`,
+/// other than the lot itself, that would wash a loss on the lot.
+pub fn wash_sale_risk<'a>(
+    token: MaybeToken,
+    lot_number: usize,
+    date: NaiveDate,
+    lots: impl Iterator<Item = (MaybeToken, &'a Lot)>,
+) -> Vec<usize> {
+    let window = chrono::Duration::days(WASH_SALE_DAYS);
+    lots.filter(|(t, lot)| {
+        *t == token
+            && lot.lot_number != lot_number
+            && is_purchase(lot)
+            && lot.acquisition.when >= date - window
+            && lot.acquisition.when <= date + window
+    })
+    .map(|(_, lot)| lot.lot_number)
+    .collect()
+}
+
+/// Matches loss sales with purchases of the same token within
+/// `WASH_SALE_DAYS`, in sale order. Each replacement lot absorbs at most its
+/// own amount, so a loss is only partly disallowed when the replacements
+/// are smaller than the lot sold.
+pub fn wash_sales(disposed: &[Sale], held: &[(MaybeToken, Lot)]) -> BTreeMap<usize, WashSale> {
+    let mut candidates = held
+        .iter()
+        .map(|(token, lot)| (*token, lot))
+        .chain(disposed.iter().map(|x| (x.token, x.lot)))
+        .filter(|(_, lot)| is_purchase(lot))
+        .map(|(token, lot)| (token, lot, lot.amount))
+        .collect::<Vec<_>>();
+    candidates.sort_by_key(|(_, lot, _)| lot.acquisition.when);
+    let mut losses = disposed
+        .iter()
+        .filter(|x| x.price < x.lot.acquisition.price())
+        .collect::<Vec<_>>();
+    losses.sort_by_key(|x| x.when);
+
+    let mut wash_sales = BTreeMap::new();
+    for sale in losses {
+        let loss =
+            Decimal::from_i128_with_scale(sale.lot.amount as i128, sale.token.decimals() as u32)
+                * (sale.lot.acquisition.price() - sale.price);
+        let window = chrono::Duration::days(WASH_SALE_DAYS);
+        let mut remaining = sale.lot.amount;
+        let mut replacements = vec![];
+        for (token, lot, available) in candidates.iter_mut() {
+            if remaining == 0 {
+                break;
+            }
+            if *token != sale.token
+                || lot.lot_number == sale.lot.lot_number
+                || *available == 0
+                || lot.acquisition.when < sale.when - window
+                || lot.acquisition.when > sale.when + window
+            {
+                continue;
+            }
+            let amount = remaining.min(*available);
+            *available -= amount;
+            remaining -= amount;
+            replacements.push((
+                lot.lot_number,
+                loss * Decimal::from(amount) / Decimal::from(sale.lot.amount),
+            ));
+        }
+        if !replacements.is_empty() {
+            wash_sales.insert(
+                sale.lot.lot_number,
+                WashSale {
+                    lot_number: sale.lot.lot_number,
+                    disallowed_loss: replacements.iter().map(|x| x.1).sum(),
+                    replacements,
+                },
+            );
+        }
+    }
+    wash_sales
+}
+
+/// One line of Form 8949.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Form8949Row {
+    pub lot_number: usize,
+    pub description: String,
+    pub acquired: NaiveDate,
+    pub sold: NaiveDate,
+    pub proceeds: Decimal,
+    pub basis: Decimal,
+    pub code: String,
+    pub adjustment: Decimal,
+    pub long_term: bool,
+}
+
+impl Form8949Row {
+    pub fn new(lot: &DisposedLot) -> Self {
+        let amount =
+            Decimal::from_i128_with_scale(lot.lot.amount as i128, lot.token.decimals() as u32);
+        Self {
+            lot_number: lot.lot.lot_number,
+            description: lot.token.format_amount(lot.lot.amount),
+            acquired: lot.lot.acquisition.when,
+            sold: lot.when,
+            proceeds: amount * lot.price(),
+            basis: amount * lot.lot.acquisition.price(),
+            code: String::new(),
+            adjustment: Decimal::ZERO,
+            long_term: is_long_term(lot.lot.acquisition.when, lot.when),
+        }
+    }
+
+    /// Reports the disallowed loss with code W in column (f).
+    pub fn with_wash_sale(self, wash_sale: Option<&WashSale>) -> Self {
+        match wash_sale {
+            Some(wash_sale) => Self {
+                code: "W".to_string(),
+                adjustment: wash_sale.disallowed_loss,
+                ..self
+            },
+            None => self,
+        }
+    }
+
+    /// Column (h), proceeds minus basis plus the adjustment in column (g).
+    pub fn gain(&self) -> Decimal {
+        self.proceeds - self.basis + self.adjustment
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Totals {
+    pub proceeds: Decimal,
+    pub basis: Decimal,
+    pub adjustment: Decimal,
+    pub gain: Decimal,
+}
+
+impl Totals {
+    pub fn new<'a>(rows: impl Iterator<Item = &'a Form8949Row>) -> Self {
+        rows.fold(Totals::default(), |acc, x| Totals {
+            proceeds: acc.proceeds + x.proceeds,
+            basis: acc.basis + x.basis,
+            adjustment: acc.adjustment + x.adjustment,
+            gain: acc.gain + x.gain(),
+        })
+    }
+}
+
+fn money(value: Decimal) -> String {
+    format!(
+        "{:.2}",
+        value.round_dp_with_strategy(2, RoundingStrategy::MidpointAwayFromZero)
+    )
+}
+
+/// Quotes a CSV field holding a separator, quote or line break.
+fn field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn write_part(csv: &mut String, title: &str, rows: &[&Form8949Row]) -> Totals {
+    writeln!(csv, "{title}").unwrap();
+    writeln!(
+        csv,
+        "(a) Description of property,(b) Date acquired,(c) Date sold or disposed of,\
+         (d) Proceeds,(e) Cost or other basis,(f) Code,(g) Amount of adjustment,\
+         (h) Gain or (loss)"
+    )
+    .unwrap();
+    for row in rows {
+        writeln!(
+            csv,
+            "{},{},{},{},{},{},{},{}",
+            field(&format!("{} (lot {})", row.description, row.lot_number)),
+            row.acquired.format("%m/%d/%Y"),
+            row.sold.format("%m/%d/%Y"),
+            money(row.proceeds),
+            money(row.basis),
+            field(&row.code),
+            if row.adjustment.is_zero() {
+                String::new()
+            } else {
+                money(row.adjustment)
+            },
+            money(row.gain()),
+        )
+        .unwrap();
+    }
+    let totals = Totals::new(rows.iter().copied());
+    writeln!(
+        csv,
+        "Totals,,,{},{},,{},{}",
+        money(totals.proceeds),
+        money(totals.basis),
+        money(totals.adjustment),
+        money(totals.gain),
+    )
+    .unwrap();
+    writeln!(csv).unwrap();
+    totals
+}
+
+/// Form 8949 in CSV, Part I short-term and Part II long-term, followed by
+/// the Schedule D lines the parts carry over to. Digital assets without a
+/// 1099 go in box C and box F.
+pub fn form_8949_csv(rows: &[Form8949Row], title: &str) -> String {
+    let mut csv = String::new();
+    writeln!(csv, "{}", field(&format!("Form 8949 {title}"))).unwrap();
+    writeln!(csv).unwrap();
+    let short_term = rows.iter().filter(|x| !x.long_term).collect::<Vec<_>>();
+    let long_term = rows.iter().filter(|x| x.long_term).collect::<Vec<_>>();
+    let short = write_part(
+        &mut csv,
+        "Part I Short-Term (box C),,,,,,,",
+        short_term.as_slice(),
+    );
+    let long = write_part(
+        &mut csv,
+        "Part II Long-Term (box F),,,,,,,",
+        long_term.as_slice(),
+    );
+    writeln!(csv, "{}", field(&format!("Schedule D {title}"))).unwrap();
+    writeln!(
+        csv,
+        "Line,(d) Proceeds,(e) Cost or other basis,(g) Adjustments,(h) Gain or (loss)"
+    )
+    .unwrap();
+    for (line, totals) in [
+        ("3 Short-term from Form 8949 box C", short),
+        ("10 Long-term from Form 8949 box F", long),
+    ] {
+        writeln!(
+            csv,
+            "{line},{},{},{},{}",
+            money(totals.proceeds),
+            money(totals.basis),
+            money(totals.adjustment),
+            money(totals.gain),
+        )
+        .unwrap();
+    }
+    writeln!(
+        csv,
+        "7 Net short-term capital gain or (loss),,,,{}",
+        money(short.gain)
+    )
+    .unwrap();
+    writeln!(
+        csv,
+        "15 Net long-term capital gain or (loss),,,,{}",
+        money(long.gain)
+    )
+    .unwrap();
+    writeln!(csv, "16 Total,,,,{}", money(short.gain + long.gain)).unwrap();
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, solana_sdk::signature::Signature, sys::db::LotAcquistion};
+
+    fn date(month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, month, day).unwrap()
+    }
+
+    fn lot(lot_number: usize, when: NaiveDate, price: i64, sol: u64) -> Lot {
+        Lot {
+            lot_number,
+            acquisition: LotAcquistion::new(
+                when,
+                Decimal::from(price),
+                LotAcquistionKind::Transaction {
+                    slot: 0,
+                    signature: Signature::default(),
+                },
+            ),
+            amount: sol * 1_000_000_000,
+        }
+    }
+
+    fn sale(lot: &Lot, when: NaiveDate, price: i64) -> Sale {
+        Sale {
+            token: MaybeToken::SOL(),
+            lot,
+            when,
+            price: Decimal::from(price),
+        }
+    }
+
+    fn held(lots: Vec<Lot>) -> Vec<(MaybeToken, Lot)> {
+        lots.into_iter().map(|x| (MaybeToken::SOL(), x)).collect()
+    }
+
+    #[test]
+    fn full_wash() {
+        let sold = lot(1, date(1, 2), 100, 2);
+        let held = held(vec![lot(2, date(3, 10), 70, 5)]);
+        let wash_sales = wash_sales(&[sale(&sold, date(3, 1), 80)], &held);
+        assert_eq!(
+            wash_sales.get(&1),
+            Some(&WashSale {
+                lot_number: 1,
+                disallowed_loss: Decimal::from(40),
+                replacements: vec![(2, Decimal::from(40))],
+            })
+        );
+    }
+
+    #[test]
+    fn partial_replacement() {
+        let sold = lot(1, date(1, 2), 100, 4);
+        let held = held(vec![lot(2, date(2, 20), 90, 1)]);
+        let wash_sales = wash_sales(&[sale(&sold, date(3, 1), 80)], &held);
+        assert_eq!(wash_sales[&1].disallowed_loss, Decimal::from(20));
+        assert_eq!(wash_sales[&1].replacements, vec![(2, Decimal::from(20))]);
+    }
+
+    #[test]
+    fn purchase_outside_window() {
+        let sold = lot(1, date(1, 2), 100, 2);
+        let held = held(vec![lot(2, date(1, 30), 70, 2), lot(3, date(4, 1), 70, 2)]);
+        assert!(wash_sales(&[sale(&sold, date(3, 1), 80)], &held).is_empty());
+        assert!(wash_sales(&[sale(&sold, date(3, 1), 120)], &held).is_empty());
+    }
+
+    #[test]
+    fn shared_replacement() {
+        let first = lot(1, date(1, 2), 100, 2);
+        let second = lot(2, date(1, 3), 100, 2);
+        let held = held(vec![lot(3, date(3, 5), 70, 3)]);
+        let wash_sales = wash_sales(
+            &[sale(&second, date(3, 2), 90), sale(&first, date(3, 1), 80)],
+            &held,
+        );
+        // the earlier sale takes 2 of the 3 tokens, the later sale the last one
+        assert_eq!(wash_sales[&1].replacements, vec![(3, Decimal::from(40))]);
+        assert_eq!(wash_sales[&2].replacements, vec![(3, Decimal::from(10))]);
+        assert_eq!(wash_sales[&2].disallowed_loss, Decimal::from(10));
+    }
+}